@@ -0,0 +1,198 @@
+//! Additive-increase/multiplicative-decrease controller for upload concurrency
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::sync::Notify;
+use tokio::time::Instant;
+use tracing::debug;
+
+/// Back off once the mean latency of a window exceeds the best one by this factor
+const LATENCY_TOLERANCE: f64 = 1.5;
+
+/// Concurrency limit adapted to observed throughput, latency and errors
+///
+/// Every window of `limit` uploads, the limit is raised by one if throughput improved,
+/// or lowered by one if latency rose. Any error halves the limit.
+pub struct AdaptiveLimit {
+  state: Mutex<State>,
+  notify: Notify,
+}
+
+struct State {
+  limit: usize,
+  max: usize,
+  in_flight: usize,
+  window: Window,
+  /// Throughput of the last window, bytes per second
+  last_throughput: Option<f64>,
+  /// Lowest mean latency of all windows
+  best_latency: Option<Duration>,
+}
+
+struct Window {
+  start: Instant,
+  completed: usize,
+  bytes: u64,
+  latency: Duration,
+}
+
+impl Window {
+  fn new(start: Instant) -> Window {
+    Window {
+      start,
+      completed: 0,
+      bytes: 0,
+      latency: Duration::ZERO,
+    }
+  }
+}
+
+impl State {
+  fn new(max: usize, now: Instant) -> State {
+    let max = max.max(1);
+    State {
+      limit: max.min(2),
+      max,
+      in_flight: 0,
+      window: Window::new(now),
+      last_throughput: None,
+      best_latency: None,
+    }
+  }
+
+  fn succeeded(&mut self, now: Instant, latency: Duration, bytes: u64) {
+    self.window.completed += 1;
+    self.window.bytes += bytes;
+    self.window.latency += latency;
+    if self.window.completed < self.limit {
+      return;
+    }
+    let elapsed = now
+      .saturating_duration_since(self.window.start)
+      .as_secs_f64();
+    let throughput = self.window.bytes as f64 / elapsed.max(f64::EPSILON);
+    let latency = self.window.latency / self.window.completed as u32;
+    let best_latency = *self.best_latency.get_or_insert(latency);
+    if latency.as_secs_f64() > best_latency.as_secs_f64() * LATENCY_TOLERANCE {
+      self.limit = (self.limit - 1).max(1);
+    } else if self.last_throughput.is_none_or(|last| throughput >= last) {
+      self.limit = (self.limit + 1).min(self.max);
+    }
+    self.best_latency = Some(best_latency.min(latency));
+    self.last_throughput = Some(throughput);
+    self.window = Window::new(now);
+  }
+
+  fn failed(&mut self, now: Instant) {
+    self.limit = (self.limit / 2).max(1);
+    self.last_throughput = None;
+    self.window = Window::new(now);
+  }
+}
+
+impl AdaptiveLimit {
+  /// Start with a limit of 2, never exceed `max`
+  pub fn new(max: usize) -> AdaptiveLimit {
+    AdaptiveLimit {
+      state: Mutex::new(State::new(max, Instant::now())),
+      notify: Notify::new(),
+    }
+  }
+
+  pub fn limit(&self) -> usize {
+    self.state.lock().unwrap().limit
+  }
+
+  /// Wait for a slot
+  pub async fn acquire(&self) {
+    loop {
+      let notified = self.notify.notified();
+      {
+        let mut state = self.state.lock().unwrap();
+        if state.in_flight < state.limit {
+          state.in_flight += 1;
+          return;
+        }
+      }
+      notified.await;
+    }
+  }
+
+  /// Release a slot after uploading `bytes` successfully in `latency`
+  pub fn succeeded(&self, latency: Duration, bytes: u64) {
+    let now = Instant::now();
+    self.release(|state| state.succeeded(now, latency, bytes));
+  }
+
+  /// Release a slot after a failed upload
+  pub fn failed(&self) {
+    let now = Instant::now();
+    self.release(|state| state.failed(now));
+  }
+
+  fn release<F: FnOnce(&mut State)>(&self, update: F) {
+    {
+      let mut state = self.state.lock().unwrap();
+      state.in_flight -= 1;
+      let old = state.limit;
+      update(&mut state);
+      if state.limit != old {
+        debug!("Upload concurrency: {old} -> {}", state.limit);
+      }
+    }
+    self.notify.notify_waiters();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use tokio::time::Instant;
+
+  use crate::aimd::State;
+
+  #[test]
+  fn increase_while_throughput_improves() {
+    let start = Instant::now();
+    let mut state = State::new(4, start);
+    let second = Duration::from_secs(1);
+    assert_eq!(state.limit, 2);
+    // Window of 2 uploads, 2 bytes per second
+    state.succeeded(start + second, second, 1);
+    state.succeeded(start + second, second, 1);
+    assert_eq!(state.limit, 3);
+    // 3 bytes per second
+    let now = start + second * 2;
+    (0..3).for_each(|_| state.succeeded(now, second, 1));
+    assert_eq!(state.limit, 4);
+    // Bounded by max
+    let now = start + second * 3;
+    (0..4).for_each(|_| state.succeeded(now, second, 1));
+    assert_eq!(state.limit, 4);
+    // Throughput drops, hold
+    let now = start + second * 13;
+    (0..4).for_each(|_| state.succeeded(now, second, 1));
+    assert_eq!(state.limit, 4);
+  }
+
+  #[test]
+  fn back_off_on_errors_and_latency() {
+    let start = Instant::now();
+    let mut state = State::new(16, start);
+    state.limit = 8;
+    state.failed(start);
+    assert_eq!(state.limit, 4);
+    let second = Duration::from_secs(1);
+    let now = start + second;
+    (0..4).for_each(|_| state.succeeded(now, second, 1));
+    assert_eq!(state.limit, 5);
+    // Latency doubled
+    let now = now + second;
+    (0..5).for_each(|_| state.succeeded(now, second * 2, 100));
+    assert_eq!(state.limit, 4);
+    (0..10).for_each(|_| state.failed(now));
+    assert_eq!(state.limit, 1);
+  }
+}
@@ -0,0 +1,446 @@
+//! Config file `config.toml` in [crate::dirs::CONFIG], with defaults and named profiles
+//!
+//! ```toml
+//! driver = "bili"
+//! block-size = "4MiB"
+//! retry = 5
+//!
+//! [drivers.bili]
+//! proxy = "socks5://127.0.0.1:1080"
+//! mirrors = ["i1.hdslb.com", "i2.hdslb.com"]
+//!
+//! [profiles.backup]
+//! encoder = "robust"
+//! retry-interval = "1m"
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::drivers::throttle::LimitSchedule;
+use crate::parser::{parse_account, parse_rate};
+use crate::proxy::ProxyOption;
+use crate::{Drivers, Encoders};
+
+const KIB: u64 = 1024;
+const MIB: u64 = 1024 * 1024;
+
+/// Options which can be set by the config file, all of them can be overridden by CLI flags
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Settings {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub driver: Option<Drivers>,
+  /// Account of the driver to upload with or login as
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub account: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub encoder: Option<Encoders>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub block_size: Option<ByteSize>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub concurrent: Option<u8>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub encode_workers: Option<u8>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub retry: Option<u8>,
+  #[serde(skip_serializing_if = "Option::is_none", with = "human_duration")]
+  pub retry_interval: Option<Duration>,
+  /// Bytes per second
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub limit_rate: Option<ByteSize>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub limit_schedule: Option<LimitSchedule>,
+  /// Proxy of all drivers, unless set in [Settings::drivers]
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub proxy: Option<ProxyOption>,
+  /// Options of each driver, keyed by driver name
+  #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+  pub drivers: BTreeMap<String, DriverSettings>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct DriverSettings {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub proxy: Option<ProxyOption>,
+  /// Hosts to download images from after the one in the url, instead of the built-in ones
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub mirrors: Vec<String>,
+}
+
+impl Settings {
+  /// Built-in defaults of CLI flags
+  pub fn builtin() -> Settings {
+    Settings {
+      driver: Some(Drivers::Bili),
+      concurrent: Some(crate::DEFAULT_CONCURRENT),
+      retry: Some(crate::DEFAULT_RETRY),
+      retry_interval: Some(crate::DEFAULT_RETRY_INTERVAL),
+      ..Settings::default()
+    }
+  }
+
+  /// Take values of `self`, fall back to `other`
+  pub fn or(self, other: Settings) -> Settings {
+    let mut drivers = other.drivers;
+    for (name, driver) in self.drivers {
+      let fallback = drivers.remove(&name).unwrap_or_default();
+      drivers.insert(
+        name,
+        DriverSettings {
+          proxy: driver.proxy.or(fallback.proxy),
+          mirrors: if driver.mirrors.is_empty() {
+            fallback.mirrors
+          } else {
+            driver.mirrors
+          },
+        },
+      );
+    }
+    Settings {
+      driver: self.driver.or(other.driver),
+      account: self.account.or(other.account),
+      encoder: self.encoder.or(other.encoder),
+      block_size: self.block_size.or(other.block_size),
+      concurrent: self.concurrent.or(other.concurrent),
+      encode_workers: self.encode_workers.or(other.encode_workers),
+      retry: self.retry.or(other.retry),
+      retry_interval: self.retry_interval.or(other.retry_interval),
+      limit_rate: self.limit_rate.or(other.limit_rate),
+      limit_schedule: self.limit_schedule.or(other.limit_schedule),
+      proxy: self.proxy.or(other.proxy),
+      drivers,
+    }
+  }
+
+  /// Proxy configured for `driver`, the driver entry comes first
+  pub fn proxy_of(&self, driver: Drivers) -> Option<&ProxyOption> {
+    self
+      .drivers
+      .iter()
+      .find(|(name, _)| driver_matches(driver, name))
+      .and_then(|(_, settings)| settings.proxy.as_ref())
+      .or(self.proxy.as_ref())
+  }
+
+  /// Download mirrors configured for `driver`, empty for the built-in ones
+  pub fn mirrors_of(&self, driver: Drivers) -> &[String] {
+    self
+      .drivers
+      .iter()
+      .find(|(name, _)| driver_matches(driver, name))
+      .map_or(&[], |(_, settings)| settings.mirrors.as_slice())
+  }
+
+  /// Validate values, and key [Settings::drivers] by canonical driver names,
+  /// so aliases of a driver are merged as one entry
+  fn validate(mut self) -> Result<Settings, String> {
+    fn check<T: PartialOrd + Display + Copy>(
+      name: &str,
+      value: Option<T>,
+      min: T,
+      max: T,
+    ) -> Result<(), String> {
+      match value {
+        Some(value) if value < min || value > max => {
+          Err(format!("{name} = {value} is out of range {min}..={max}"))
+        }
+        _ => Ok(()),
+      }
+    }
+    check("concurrent", self.concurrent, 2, 63)?;
+    check("encode-workers", self.encode_workers, 1, 64)?;
+    check(
+      "block-size",
+      self.block_size.map(|i| i.0),
+      16 * KIB,
+      256 * MIB,
+    )?;
+    check("limit-rate", self.limit_rate.map(|i| i.0), KIB, u64::MAX)?;
+    if let Some(account) = &self.account {
+      parse_account(account).map_err(|e| format!("account = {account:?}: {e}"))?;
+    }
+    let mut drivers = BTreeMap::new();
+    for (name, settings) in std::mem::take(&mut self.drivers) {
+      let driver = Drivers::value_variants()
+        .iter()
+        .find(|driver| driver_matches(**driver, &name))
+        .ok_or_else(|| format!("Unknown driver `{name}` in [drivers]"))?;
+      if let Some(mirror) = settings.mirrors.iter().find(|i| !is_host(i)) {
+        return Err(format!(
+          "mirrors = {mirror:?} in [drivers.{name}] is not a host name"
+        ));
+      }
+      drivers.insert(driver.to_string(), settings);
+    }
+    self.drivers = drivers;
+    Ok(self)
+  }
+}
+
+fn is_host(host: &str) -> bool {
+  !host.is_empty()
+    && host
+      .chars()
+      .all(|i| i.is_ascii_alphanumeric() || i == '.' || i == '-' || i == ':')
+}
+
+fn driver_matches(driver: Drivers, name: &str) -> bool {
+  driver
+    .to_possible_value()
+    .is_some_and(|i| i.matches(name, true))
+}
+
+/// Parsed config file
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Config {
+  pub defaults: Settings,
+  pub profiles: BTreeMap<String, Settings>,
+}
+
+impl Config {
+  pub fn default_path() -> PathBuf {
+    crate::dirs::CONFIG.join("config.toml")
+  }
+
+  /// Load the config at `path`, or the default path if absent
+  ///
+  /// A missing file at the default path is an empty config.
+  pub fn load(path: Option<&Path>) -> Result<(PathBuf, Config), ConfigError> {
+    let (path, explicit) = match path {
+      Some(path) => (path.to_path_buf(), true),
+      None => (Config::default_path(), false),
+    };
+    if !explicit && !path.exists() {
+      return Ok((path, Config::default()));
+    }
+    let raw = std::fs::read_to_string(&path).map_err(|source| ConfigError::Io {
+      path: path.clone(),
+      source,
+    })?;
+    let config = Config::parse(&raw).map_err(|reason| ConfigError::Invalid {
+      path: path.clone(),
+      reason,
+    })?;
+    Ok((path, config))
+  }
+
+  fn parse(raw: &str) -> Result<Config, String> {
+    let mut table: toml::value::Table = toml::from_str(raw).map_err(|e| e.to_string())?;
+    let profiles = match table.remove("profiles") {
+      Some(profiles) => profiles
+        .try_into::<BTreeMap<String, Settings>>()
+        .map_err(|e| format!("In [profiles]: {e}"))?,
+      None => BTreeMap::new(),
+    };
+    let defaults = toml::Value::Table(table)
+      .try_into::<Settings>()
+      .map_err(|e| e.to_string())?
+      .validate()?;
+    let profiles = profiles
+      .into_iter()
+      .map(|(name, profile)| match profile.validate() {
+        Ok(profile) => Ok((name, profile)),
+        Err(e) => Err(format!("In [profiles.{name}]: {e}")),
+      })
+      .collect::<Result<_, _>>()?;
+    Ok(Config { defaults, profiles })
+  }
+
+  /// Settings of `profile` falling back to the defaults, or just the defaults
+  pub fn settings(&self, profile: Option<&str>) -> Result<Settings, ConfigError> {
+    match profile {
+      None => Ok(self.defaults.clone()),
+      Some(name) => {
+        let profile = self
+          .profiles
+          .get(name)
+          .ok_or_else(|| ConfigError::Profile {
+            name: name.to_string(),
+            available: self.profiles.keys().cloned().collect(),
+          })?;
+        Ok(profile.clone().or(self.defaults.clone()))
+      }
+    }
+  }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ConfigError {
+  #[error("Failed to read config {path:?}: {source}")]
+  Io {
+    path: PathBuf,
+    #[source]
+    source: std::io::Error,
+  },
+  #[error("Invalid config {path:?}: {reason}")]
+  Invalid { path: PathBuf, reason: String },
+  #[error("Profile `{name}` is not found, available profiles: {available:?}")]
+  Profile {
+    name: String,
+    available: Vec<String>,
+  },
+}
+
+/// Bytes, written as a number or with a unit like `4MiB`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ByteSize(pub u64);
+
+impl Display for ByteSize {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match self.0 {
+      0 => write!(f, "0"),
+      n if n.is_multiple_of(MIB) => write!(f, "{}MiB", n / MIB),
+      n if n.is_multiple_of(KIB) => write!(f, "{}KiB", n / KIB),
+      n => write!(f, "{n}"),
+    }
+  }
+}
+
+impl Serialize for ByteSize {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    if self.0.is_multiple_of(KIB) && self.0 != 0 {
+      serializer.collect_str(self)
+    } else {
+      serializer.serialize_u64(self.0)
+    }
+  }
+}
+
+impl<'de> Deserialize<'de> for ByteSize {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+      Number(u64),
+      Text(String),
+    }
+    match Raw::deserialize(deserializer)? {
+      Raw::Number(n) => Ok(ByteSize(n)),
+      // `/s` is accepted for rates
+      Raw::Text(text) => parse_rate(&text)
+        .map(ByteSize)
+        .map_err(serde::de::Error::custom),
+    }
+  }
+}
+
+/// Durations written like `10s` or `1m 30s`
+mod human_duration {
+  use std::time::Duration;
+
+  use serde::{Deserialize, Deserializer, Serializer};
+
+  pub fn serialize<S: Serializer>(
+    value: &Option<Duration>,
+    serializer: S,
+  ) -> Result<S::Ok, S::Error> {
+    match value {
+      Some(duration) => serializer.collect_str(&humantime::format_duration(*duration)),
+      None => serializer.serialize_none(),
+    }
+  }
+
+  pub fn deserialize<'de, D: Deserializer<'de>>(
+    deserializer: D,
+  ) -> Result<Option<Duration>, D::Error> {
+    let raw = String::deserialize(deserializer)?;
+    humantime::parse_duration(&raw)
+      .map(Some)
+      .map_err(serde::de::Error::custom)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use crate::config::{ByteSize, Config, Settings};
+  use crate::proxy::ProxyOption;
+  use crate::{Drivers, Encoders};
+
+  const EXAMPLE: &str = r#"
+driver = "bili"
+block-size = "4MiB"
+retry = 5
+limit-rate = "2MiB/s"
+
+[drivers.bili]
+proxy = "socks5://127.0.0.1:1080"
+mirrors = ["i2.hdslb.com"]
+
+[profiles.backup]
+encoder = "robust"
+retry-interval = "1m"
+
+[profiles.direct]
+retry = 1
+
+[profiles.direct.drivers.bilibili]
+proxy = "direct"
+"#;
+
+  #[test]
+  fn parse_profiles() {
+    let config = Config::parse(EXAMPLE).unwrap();
+    assert_eq!(config.defaults.driver, Some(Drivers::Bili));
+    assert_eq!(config.defaults.block_size, Some(ByteSize(4 * 1024 * 1024)));
+    assert_eq!(config.defaults.limit_rate, Some(ByteSize(2 * 1024 * 1024)));
+    assert_eq!(config.profiles.len(), 2);
+
+    let backup = config.settings(Some("backup")).unwrap();
+    assert_eq!(backup.encoder, Some(Encoders::Robust));
+    assert_eq!(backup.retry, Some(5));
+    assert_eq!(backup.retry_interval, Some(Duration::from_secs(60)));
+    assert_eq!(
+      backup.proxy_of(Drivers::Bili).unwrap().to_string(),
+      "socks5://127.0.0.1:1080"
+    );
+    assert_eq!(backup.mirrors_of(Drivers::Bili), ["i2.hdslb.com"]);
+
+    let direct = config.settings(Some("direct")).unwrap();
+    assert_eq!(direct.retry, Some(1));
+    assert_eq!(direct.proxy_of(Drivers::Bili), Some(&ProxyOption::Direct));
+    // The profile sets no mirrors of its own
+    assert_eq!(direct.mirrors_of(Drivers::Bili), ["i2.hdslb.com"]);
+
+    assert!(config.settings(Some("missing")).is_err());
+    assert_eq!(config.settings(None).unwrap(), config.defaults);
+  }
+
+  #[test]
+  fn reject_invalid() {
+    for raw in [
+      "concurrent = 100",
+      "block-size = \"1KiB\"",
+      "unknown = 1",
+      "[drivers.imgur]\nproxy = \"direct\"",
+      "[profiles.fast]\nconcurrent = 1",
+      "proxy = \"127.0.0.1\"",
+      "retry-interval = \"soon\"",
+      "account = \"../work\"",
+      "[drivers.bili]\nmirrors = [\"https://i0.hdslb.com/\"]",
+    ] {
+      assert!(Config::parse(raw).is_err(), "{raw}");
+    }
+  }
+
+  #[test]
+  fn show_round_trip() {
+    let settings = Config::parse(EXAMPLE)
+      .unwrap()
+      .settings(Some("backup"))
+      .unwrap()
+      .or(Settings::builtin());
+    let shown = toml::to_string(&settings).unwrap();
+    assert!(shown.contains("block-size = \"4MiB\""), "{shown}");
+    let parsed = Config::parse(&shown).unwrap();
+    assert_eq!(parsed.defaults, settings);
+  }
+}
@@ -11,7 +11,7 @@ lazy_static! {
   pub(super) static ref CACHE: PathBuf = cache_dir();
 }
 
-pub(self) fn debug_work_dir() -> Option<PathBuf> {
+fn debug_work_dir() -> Option<PathBuf> {
   if !cfg!(debug_assertions) {
     return None;
   }
@@ -19,11 +19,11 @@ pub(self) fn debug_work_dir() -> Option<PathBuf> {
   Some(cur_dir.join("./cutis-work"))
 }
 
-pub(self) fn project_dir() -> Option<ProjectDirs> {
+fn project_dir() -> Option<ProjectDirs> {
   ProjectDirs::from("me", "colerar", "cutis")
 }
 
-pub(self) fn get_some_dir(name: &str, config_project_dir: fn(&ProjectDirs) -> &Path) -> PathBuf {
+fn get_some_dir(name: &str, config_project_dir: fn(&ProjectDirs) -> &Path) -> PathBuf {
   let path = format!("./{name}");
   debug_work_dir()
     .map(|mut i| {
@@ -43,17 +43,17 @@ pub(self) fn get_some_dir(name: &str, config_project_dir: fn(&ProjectDirs) -> &P
       cur_dir.push(&path);
       Some(cur_dir)
     })
-    .expect(&*format!("Failed to get {name} dir"))
+    .unwrap_or_else(|| panic!("Failed to get {name} dir"))
 }
 
-pub(self) fn config_dir() -> PathBuf {
+fn config_dir() -> PathBuf {
   get_some_dir("config", |i| i.config_dir())
 }
 
-pub(self) fn data_dir() -> PathBuf {
+fn data_dir() -> PathBuf {
   get_some_dir("data", |i| i.data_dir())
 }
 
-pub(self) fn cache_dir() -> PathBuf {
+fn cache_dir() -> PathBuf {
   get_some_dir("cache", |i| i.cache_dir())
 }
@@ -87,9 +87,194 @@ pub struct AlbumUploadData {
   pub image_height: Option<u32>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct AlbumListRsp {
+  pub code: i32,
+  pub message: Option<String>,
+  pub data: Option<AlbumList>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AlbumList {
+  #[serde(default)]
+  pub items: Vec<AlbumDoc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AlbumDetailRsp {
+  pub code: i32,
+  pub message: Option<String>,
+  pub data: Option<AlbumDetail>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AlbumDetail {
+  pub item: AlbumDoc,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AlbumCreateRsp {
+  pub code: i32,
+  pub message: Option<String>,
+  pub data: Option<AlbumCreated>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AlbumCreated {
+  pub doc_id: u64,
+}
+
+/// A post of the album
+#[derive(Debug, Deserialize)]
+pub struct AlbumDoc {
+  pub doc_id: u64,
+  /// Number of pictures, the list may carry fewer
+  #[serde(default)]
+  pub count: usize,
+  #[serde(default)]
+  pub pictures: Vec<AlbumPicture>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AlbumPicture {
+  pub img_src: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LogOutRsp {
+  pub code: i32,
+  pub message: Option<String>,
+  pub status: Option<bool>,
+  #[serde(rename = "ts")]
+  pub timestamp: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CountryListRsp {
+  pub code: i32,
+  pub data: Option<CountryList>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CountryList {
+  pub common: Vec<Country>,
+  pub others: Vec<Country>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Country {
+  pub id: u32,
+  #[serde(rename = "cname")]
+  pub name: String,
+  /// Calling code, e.g. `86`
+  #[serde(rename = "country_id")]
+  pub calling_code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CaptchaRsp {
+  pub code: i32,
+  pub message: Option<String>,
+  pub data: Option<Captcha>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Captcha {
+  #[serde(rename = "type")]
+  pub kind: String,
+  pub token: String,
+  pub geetest: Option<Geetest>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Geetest {
+  pub gt: String,
+  pub challenge: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SmsSendRsp {
+  pub code: i32,
+  pub message: Option<String>,
+  pub data: Option<SmsSendData>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SmsSendData {
+  pub captcha_key: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SmsLoginRsp {
+  pub code: i32,
+  pub message: Option<String>,
+  pub data: Option<LoginTokenData>,
+}
+
+/// Token returned by SMS and password logins, to refresh cookies later
+#[derive(Debug, Deserialize)]
+pub struct LoginTokenData {
+  pub refresh_token: Option<String>,
+}
+
+/// Public key and salt to encrypt passwords
+#[derive(Debug, Deserialize)]
+pub struct RsaKeyRsp {
+  pub hash: String,
+  /// PEM
+  pub key: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasswordLoginRsp {
+  pub code: i32,
+  pub message: Option<String>,
+  pub data: Option<PasswordLoginData>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasswordLoginData {
+  /// Non-zero if further verification is required at [PasswordLoginData::url]
+  pub status: i32,
+  pub message: Option<String>,
+  pub url: Option<String>,
+  pub refresh_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CookieInfoRsp {
+  pub code: i32,
+  pub message: Option<String>,
+  pub data: Option<CookieInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CookieInfo {
+  /// Whether cookies should be refreshed
+  pub refresh: bool,
+  /// Server time in milliseconds
+  pub timestamp: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CookieRefreshRsp {
+  pub code: i32,
+  pub message: Option<String>,
+  pub data: Option<LoginTokenData>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConfirmRefreshRsp {
+  pub code: i32,
+  pub message: Option<String>,
+}
+
 #[cfg(test)]
 mod tests {
-  use super::LoginQrRsp;
+  use super::{
+    AlbumCreateRsp, AlbumDetailRsp, AlbumListRsp, AlbumUploadRsp, CaptchaRsp, CookieInfoRsp,
+    CountryListRsp, LogOutRsp, LoginQrRsp, PasswordLoginRsp, SmsSendRsp,
+  };
 
   fn de_qr_login_rsp(json: &str) {
     let rsp: LoginQrRsp = serde_json::from_str(json).unwrap();
@@ -113,4 +298,114 @@ mod tests {
   fn de_qr_login_rsp_null_data() {
     de_qr_login_rsp(r#"{"code":1,"message":"asdfa","ts":123123,"status":true,"data": null}"#);
   }
+
+  #[test]
+  fn de_dynamic_upload_rsp() {
+    let rsp: AlbumUploadRsp = serde_json::from_str(
+      r#"{"code":0,"message":"0","ttl":1,"data":{"image_url":
+      "http://i0.hdslb.com/bfs/new_dyn/4b1a9b7a8e0d2d6f1c3a2f2a4a3b0e5c1234567.png",
+      "image_width":256,"image_height":256,"size":1024.5}}"#,
+    )
+    .unwrap();
+    let data = rsp.data.unwrap();
+    assert!(data.image_url.unwrap().contains("/bfs/new_dyn/"));
+    assert_eq!(data.image_width, Some(256));
+  }
+
+  #[test]
+  fn de_album_create_rsp() {
+    let rsp: AlbumCreateRsp = serde_json::from_str(
+      r#"{"code":0,"msg":"success","message":"success","data":{"doc_id":5678}}"#,
+    )
+    .unwrap();
+    assert_eq!(rsp.data.unwrap().doc_id, 5678);
+    let rsp: AlbumCreateRsp =
+      serde_json::from_str(r#"{"code":-101,"message":"账号未登录","data":null}"#).unwrap();
+    assert!(rsp.data.is_none());
+  }
+
+  #[test]
+  fn de_album_list_rsp() {
+    let rsp: AlbumListRsp = serde_json::from_str(
+      r#"{"code":0,"msg":"success","message":"success","data":{"items":[{"doc_id":1234,
+      "poster_uid":1,"title":"","description":"","count":2,"ctime":1662556260,
+      "pictures":[{"img_src":"https://i0.hdslb.com/bfs/album/2569aaaa4f9b28787cf1f0c5b1134cc7e0900000.png",
+      "img_width":256,"img_height":256,"img_size":12.5}]}]}}"#,
+    )
+    .unwrap();
+    let items = rsp.data.unwrap().items;
+    assert_eq!(items[0].doc_id, 1234);
+    assert_eq!(items[0].count, 2);
+    assert_eq!(items[0].pictures.len(), 1);
+    let rsp: AlbumListRsp = serde_json::from_str(r#"{"code":0,"data":{}}"#).unwrap();
+    assert!(rsp.data.unwrap().items.is_empty());
+    let rsp: AlbumDetailRsp = serde_json::from_str(
+      r#"{"code":0,"data":{"user":{"uid":1},"item":{"doc_id":1234,"pictures":[
+      {"img_src":"https://i0.hdslb.com/bfs/album/a.png"},{"img_src":"https://i0.hdslb.com/bfs/album/b.png"}]}}}"#,
+    )
+    .unwrap();
+    assert_eq!(rsp.data.unwrap().item.pictures.len(), 2);
+  }
+
+  #[test]
+  fn de_log_out_rsp() {
+    let rsp: LogOutRsp = serde_json::from_str(
+      r#"{"code":0,"status":true,"ts":1662556260,"data":{"redirectUrl":"https://www.bilibili.com"}}"#,
+    )
+    .unwrap();
+    assert_eq!(rsp.code, 0);
+    let rsp: LogOutRsp =
+      serde_json::from_str(r#"{"code":2202,"message":"csrf 请求非法","status":false}"#).unwrap();
+    assert_eq!(rsp.code, 2202);
+  }
+
+  #[test]
+  fn de_sms_login_flow() {
+    let rsp: CountryListRsp = serde_json::from_str(
+      r#"{"code":0,"data":{"common":[{"id":1,"cname":"中国大陆","country_id":"86"}],
+      "others":[{"id":2,"cname":"美国","country_id":"1"}]}}"#,
+    )
+    .unwrap();
+    let countries = rsp.data.unwrap();
+    assert_eq!(countries.common[0].calling_code, "86");
+    assert_eq!(countries.others[0].name, "美国");
+
+    let rsp: CaptchaRsp = serde_json::from_str(
+      r#"{"code":0,"message":"0","ttl":1,"data":{"type":"geetest","token":"t0k3n",
+      "geetest":{"challenge":"c4a1","gt":"ac59"},"tencent":{"appid":""}}}"#,
+    )
+    .unwrap();
+    let captcha = rsp.data.unwrap();
+    assert_eq!(captcha.kind, "geetest");
+    assert_eq!(captcha.geetest.unwrap().challenge, "c4a1");
+
+    let rsp: SmsSendRsp =
+      serde_json::from_str(r#"{"code":0,"message":"0","ttl":1,"data":{"captcha_key":"0b8a"}}"#)
+        .unwrap();
+    assert_eq!(rsp.data.unwrap().captcha_key, "0b8a");
+  }
+
+  #[test]
+  fn de_password_login_rsp() {
+    let rsp: PasswordLoginRsp = serde_json::from_str(
+      r#"{"code":0,"message":"0","ttl":1,"data":{"status":2,"message":"本次登录环境存在风险",
+      "url":"https://passport.bilibili.com/account/mobile/security/managephone/phone/verify",
+      "refresh_token":"","timestamp":1662556260}}"#,
+    )
+    .unwrap();
+    let data = rsp.data.unwrap();
+    assert_eq!(data.status, 2);
+    assert!(data.url.is_some());
+  }
+
+  #[test]
+  fn de_cookie_info_rsp() {
+    let rsp: CookieInfoRsp = serde_json::from_str(
+      r#"{"code":0,"message":"0","ttl":1,"data":{"refresh":true,"timestamp":1684466082062}}"#,
+    )
+    .unwrap();
+    let info = rsp.data.unwrap();
+    assert!(info.refresh);
+    assert_eq!(info.timestamp, 1684466082062);
+  }
 }
@@ -1,42 +1,123 @@
 use std::fmt::Debug;
-use std::fs::{File, OpenOptions};
-use std::io::{BufReader, BufWriter};
-use std::ops::Add;
-use std::path::PathBuf;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
 use std::{fs, io};
 
 use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
 use bytes::Bytes;
-use cookie::time::OffsetDateTime;
+use chrono::{DateTime, TimeZone, Utc};
+use clap::{PossibleValue, ValueEnum};
 use cookie_store::{Cookie, CookieDomain, CookieExpiration, CookieStore};
+use dialoguer::theme::ColorfulTheme;
+use dialoguer::{Input, Select};
 use log::error;
 use regex::Regex;
 use reqwest::header::{ORIGIN, REFERER};
 use reqwest::multipart::{Form, Part};
 use reqwest::{Client, ClientBuilder, Url};
 use reqwest_cookie_store::CookieStoreRwLock;
+use rsa::pkcs8::DecodePublicKey;
+use rsa::{Pkcs1v15Encrypt, RsaPublicKey};
 use tokio::time;
 use tokio::time::timeout;
 use tracing::{debug, info, warn};
 
-use super::bili::url::ALBUM_UPLOAD_URL;
-use super::Driver;
+use super::bili::url::{ALBUM_UPLOAD_URL, DYNAMIC_UPLOAD_URL};
+use super::{throttle, Capabilities, Driver, ImageFormat, Recovery, UserInfo, DEFAULT_ACCOUNT};
+use crate::qr::QrOptions;
 
-use self::data::{AlbumUploadRsp, LoginQrRsp, QrRsp, SelfInfoRsp};
-use self::url::{BASIC_INFO_GET_URL, FEED_DOMAIN, LOGIN_QRCODE_GET_WEB_URL, LOGIN_WEB_QRCODE_URL};
+use self::data::{
+  AlbumCreateRsp, AlbumDetailRsp, AlbumDoc, AlbumListRsp, AlbumUploadRsp, CaptchaRsp, Country,
+  CountryListRsp, LogOutRsp, LoginQrRsp, PasswordLoginRsp, QrRsp, RsaKeyRsp, SelfInfoRsp,
+  SmsLoginRsp, SmsSendRsp,
+};
+use self::url::{
+  ALBUM_CREATE_URL, ALBUM_INFO_GET_URL, BASIC_INFO_GET_URL, FEED_DOMAIN, GET_CALLING_CODE_URL,
+  LOGIN_QRCODE_GET_WEB_URL, LOGIN_WEB_QRCODE_URL, LOGIN_WEB_SMS_URL, LOGIN_WEB_URL, LOG_OUT_URL,
+  QUERY_CAPTCHA_URL, RSA_GET_WEB_URL, SEND_SMS_URL, SPACE_ALBUM_LIST_URL,
+};
 
 pub mod data;
+mod refresh;
 mod url;
 
-pub(self) const MAC_SAFARI_USER_AGENT: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 12_5) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/15.6 Safari/605.1.15";
+/// Page to solve a geetest captcha by `gt` and `challenge` in the browser
+const GEETEST_VALIDATOR_URL: &str = "https://kuresaru.github.io/geetest-validator/";
+
+const MAC_SAFARI_USER_AGENT: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 12_5) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/15.6 Safari/605.1.15";
+
+/// Geetest captcha solved by the user
+struct SolvedCaptcha {
+  token: String,
+  challenge: String,
+  validate: String,
+}
+
+impl SolvedCaptcha {
+  fn seccode(&self) -> String {
+    format!("{}|jordan", self.validate)
+  }
+}
+
+/// Encrypt `hash` + `password` with the RSA public key in PEM, as base64
+fn encrypt_password(key: &str, hash: &str, password: &str) -> Result<String> {
+  let key = RsaPublicKey::from_public_key_pem(key).context("Invalid RSA public key")?;
+  let plain = format!("{hash}{password}");
+  let encrypted = key
+    .encrypt(&mut rand::thread_rng(), Pkcs1v15Encrypt, plain.as_bytes())
+    .context("Failed to encrypt password")?;
+  Ok(BASE64.encode(encrypted))
+}
+
+/// Posts per page of the album list
+const ALBUM_PAGE_SIZE: usize = 30;
+
+/// Code of responses when the session is invalid
+const NOT_LOGIN_CODE: i32 = -101;
+
+/// Where images are uploaded to
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum BiliRoute {
+  /// The album, images are kept in `bfs/album`
+  Album,
+  /// Images of dynamics, kept in `bfs/new_dyn`
+  Dynamic,
+  /// The album, falling back to dynamic while the album is throttled or rejects images
+  #[default]
+  Auto,
+}
+
+impl ValueEnum for BiliRoute {
+  fn value_variants<'a>() -> &'a [Self] {
+    &[Self::Album, Self::Dynamic, Self::Auto]
+  }
+
+  fn to_possible_value<'a>(&self) -> Option<PossibleValue<'a>> {
+    match self {
+      Self::Album => Some(PossibleValue::new("album")),
+      Self::Dynamic => Some(PossibleValue::new("dynamic")),
+      Self::Auto => Some(PossibleValue::new("auto")),
+    }
+  }
+}
 
 pub struct BiliClient {
   pub reqwest: Client,
   cookie_path: PathBuf,
   pub cookie: Arc<CookieStoreRwLock>,
+  /// Also held while refreshing, so concurrent uploads refresh once
+  last_refresh_check: tokio::sync::Mutex<Option<time::Instant>>,
+  route: BiliRoute,
+  /// Uploads of [BiliRoute::Auto] skip the album until then
+  album_throttled_until: std::sync::Mutex<Option<time::Instant>>,
+  /// Hosts to download from after the one in the url, [CDN_MIRRORS] if empty
+  mirrors: Vec<String>,
 }
 
 impl BiliClient {
@@ -45,27 +126,21 @@ impl BiliClient {
   }
 
   pub async fn new() -> Result<BiliClient, anyhow::Error> {
-    BiliClient::new_with_options(|i| i).await
+    BiliClient::new_with_options(DEFAULT_ACCOUNT, |i| i).await
   }
 
-  pub async fn new_with_options<F>(option: F) -> Result<BiliClient, anyhow::Error>
+  /// Client of `account`, whose cookies are loaded from [BiliClient::cookie_path]
+  pub async fn new_with_options<F>(account: &str, option: F) -> Result<BiliClient, anyhow::Error>
   where
     F: FnOnce(ClientBuilder) -> ClientBuilder + Send,
   {
-    let mut path = crate::dirs::DATA.clone();
-    path.push("./bili_cookies.jsonl");
-    if let Some(parent) = path.parent() {
-      fs::create_dir_all(parent).expect("Failed to create dir");
-    }
-    let file = OpenOptions::new()
-      .create(true)
-      .read(true)
-      .write(true)
-      .open(&path)
-      .unwrap();
-    let cookie_store = {
-      let file = BufReader::new(&file);
-      CookieStore::load_json(file).unwrap()
+    let path = BiliClient::cookie_path(account);
+    let cookie_store = match File::open(&path) {
+      Ok(file) => CookieStore::load_json(BufReader::new(file))
+        .map_err(|err| anyhow!("{err}"))
+        .with_context(|| format!("Failed to load cookies from {path:?}"))?,
+      Err(err) if err.kind() == io::ErrorKind::NotFound => CookieStore::default(),
+      Err(err) => Err(err).with_context(|| format!("Failed to open {path:?}"))?,
     };
     let cookie_store = CookieStoreRwLock::new(cookie_store);
     let cookie_store = Arc::new(cookie_store);
@@ -82,9 +157,77 @@ impl BiliClient {
       reqwest: client,
       cookie_path: path,
       cookie: Arc::clone(&cookie_store),
+      last_refresh_check: Default::default(),
+      route: BiliRoute::default(),
+      album_throttled_until: Default::default(),
+      mirrors: Vec::new(),
     })
   }
 
+  pub fn with_route(mut self, route: BiliRoute) -> BiliClient {
+    self.route = route;
+    self
+  }
+
+  /// Download from `mirrors` instead of [CDN_MIRRORS], unless empty
+  pub fn with_mirrors(mut self, mirrors: Vec<String>) -> BiliClient {
+    self.mirrors = mirrors;
+    self
+  }
+
+  /// Cookies of the default account are kept in `bili_cookies.jsonl` for compatibility,
+  /// others in `bili_accounts/<account>.jsonl`
+  fn cookie_path(account: &str) -> PathBuf {
+    if account == DEFAULT_ACCOUNT {
+      crate::dirs::DATA.join("bili_cookies.jsonl")
+    } else {
+      BiliClient::accounts_dir().join(format!("{account}.jsonl"))
+    }
+  }
+
+  fn accounts_dir() -> PathBuf {
+    crate::dirs::DATA.join("bili_accounts")
+  }
+
+  /// Accounts with saved cookies, sorted by name
+  pub fn accounts() -> io::Result<Vec<String>> {
+    let mut accounts = Vec::new();
+    if BiliClient::cookie_path(DEFAULT_ACCOUNT).is_file() {
+      accounts.push(DEFAULT_ACCOUNT.to_string());
+    }
+    let dir = match fs::read_dir(BiliClient::accounts_dir()) {
+      Ok(dir) => dir,
+      Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(accounts),
+      Err(err) => return Err(err),
+    };
+    for entry in dir {
+      let path = entry?.path();
+      if path.extension().and_then(|i| i.to_str()) != Some("jsonl") {
+        continue;
+      }
+      if let Some(name) = path.file_stem().and_then(|i| i.to_str()) {
+        accounts.push(name.to_string());
+      }
+    }
+    accounts.sort();
+    accounts.dedup();
+    Ok(accounts)
+  }
+
+  /// Remove saved cookies of `account`, return `false` if there are none
+  pub fn remove_account(account: &str) -> io::Result<bool> {
+    let path = BiliClient::cookie_path(account);
+    match fs::remove_file(path.with_extension("token")) {
+      Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+      _ => {}
+    }
+    match fs::remove_file(path) {
+      Ok(()) => Ok(true),
+      Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+      Err(err) => Err(err),
+    }
+  }
+
   async fn get_csrf(&self) -> Result<String, GetCsrfError> {
     let arc = Arc::clone(&self.cookie);
     let lock = &arc.read().map_err(|_| GetCsrfError::LockPoison())?;
@@ -101,8 +244,16 @@ impl BiliClient {
       .cookie
       .write()
       .map_err(|_| SaveCookieError::LockPoison())?;
+    if let Some(parent) = self.cookie_path.parent().filter(|i| !Path::exists(i)) {
+      fs::create_dir_all(parent)?;
+    }
     let mut w = File::create(&self.cookie_path).map(BufWriter::new)?;
-    cookie.save_json(&mut w)?;
+    // Cookies pasted from a browser carry no expiry, keep them as well
+    for cookie in cookie.iter_unexpired() {
+      let line = serde_json::to_string(cookie).map_err(io::Error::from)?;
+      writeln!(w, "{line}")?;
+    }
+    w.flush()?;
     Ok(())
   }
 
@@ -131,6 +282,186 @@ impl BiliClient {
     Ok(login_rsp)
   }
 
+  /// Invalidate the session on the server
+  async fn log_out_remote(&self) -> Result<LogOutRsp, LogOutError> {
+    let csrf = self.get_csrf().await?;
+    let rsp = self
+      .reqwest()
+      .post(LOG_OUT_URL)
+      .form(&[("biliCSRF", csrf)])
+      .send()
+      .await?;
+    let rsp: LogOutRsp = rsp.json().await?;
+    Ok(rsp)
+  }
+
+  /// Countries with calling codes, common ones first
+  async fn get_countries(&self) -> Result<Vec<Country>> {
+    let rsp: CountryListRsp = self
+      .reqwest()
+      .get(GET_CALLING_CODE_URL)
+      .send()
+      .await?
+      .json()
+      .await?;
+    let list = rsp
+      .data
+      .with_context(|| format!("Country list is none, code {}", rsp.code))?;
+    Ok(list.common.into_iter().chain(list.others).collect())
+  }
+
+  /// Get a captcha and let the user solve it in a browser
+  async fn solve_captcha(&self, theme: &ColorfulTheme) -> Result<SolvedCaptcha> {
+    let rsp: CaptchaRsp = self
+      .reqwest()
+      .get(QUERY_CAPTCHA_URL)
+      .query(&[("source", "main_web")])
+      .send()
+      .await?
+      .json()
+      .await?;
+    let captcha = match rsp.data {
+      Some(captcha) if rsp.code == 0 => captcha,
+      _ => Err(anyhow!(
+        "Failed to get captcha, code {}: {}",
+        rsp.code,
+        rsp.message.unwrap_or_default()
+      ))?,
+    };
+    let geetest = match (captcha.geetest, captcha.kind.as_str()) {
+      (Some(geetest), "geetest") => geetest,
+      _ => Err(anyhow!("Unsupported captcha type: {}", captcha.kind))?,
+    };
+    info!("Please solve the captcha in a browser: {GEETEST_VALIDATOR_URL}");
+    info!("gt: {}", geetest.gt);
+    info!("challenge: {}", geetest.challenge);
+    let validate: String = Input::with_theme(theme)
+      .with_prompt("validate")
+      .interact_text()?;
+    Ok(SolvedCaptcha {
+      token: captcha.token,
+      challenge: geetest.challenge,
+      validate: validate.trim().to_string(),
+    })
+  }
+
+  /// Send the code to `tel`, return the key to login with
+  async fn send_sms(
+    &self,
+    calling_code: &str,
+    tel: &str,
+    captcha: &SolvedCaptcha,
+  ) -> Result<String> {
+    let rsp: SmsSendRsp = self
+      .reqwest()
+      .post(SEND_SMS_URL)
+      .form(&[
+        ("cid", calling_code),
+        ("tel", tel),
+        ("source", "main_web"),
+        ("token", &captcha.token),
+        ("challenge", &captcha.challenge),
+        ("validate", &captcha.validate),
+        ("seccode", &captcha.seccode()),
+      ])
+      .send()
+      .await?
+      .json()
+      .await?;
+    match rsp.data {
+      Some(data) if rsp.code == 0 => Ok(data.captcha_key),
+      _ => Err(anyhow!(
+        "Failed to send SMS, code {}: {}",
+        rsp.code,
+        rsp.message.unwrap_or_default()
+      )),
+    }
+  }
+
+  async fn login_sms(
+    &self,
+    calling_code: &str,
+    tel: &str,
+    code: &str,
+    captcha_key: &str,
+  ) -> Result<Option<String>> {
+    let rsp: SmsLoginRsp = self
+      .reqwest()
+      .post(LOGIN_WEB_SMS_URL)
+      .form(&[
+        ("cid", calling_code),
+        ("tel", tel),
+        ("code", code),
+        ("source", "main_web"),
+        ("captcha_key", captcha_key),
+        ("keep", "true"),
+      ])
+      .send()
+      .await?
+      .json()
+      .await?;
+    if rsp.code != 0 {
+      return Err(anyhow!(
+        "Failed to login with SMS, code {}: {}",
+        rsp.code,
+        rsp.message.unwrap_or_default()
+      ));
+    }
+    Ok(rsp.data.and_then(|i| i.refresh_token))
+  }
+
+  async fn get_rsa_key(&self) -> Result<RsaKeyRsp> {
+    let rsp = self
+      .reqwest()
+      .get(RSA_GET_WEB_URL)
+      .query(&[("act", "getKey")])
+      .send()
+      .await?
+      .json()
+      .await?;
+    Ok(rsp)
+  }
+
+  async fn login_password(
+    &self,
+    username: &str,
+    encrypted: &str,
+    captcha: &SolvedCaptcha,
+  ) -> Result<Option<String>> {
+    let rsp: PasswordLoginRsp = self
+      .reqwest()
+      .post(LOGIN_WEB_URL)
+      .form(&[
+        ("username", username),
+        ("password", encrypted),
+        ("keep", "0"),
+        ("source", "main_web"),
+        ("token", &captcha.token),
+        ("challenge", &captcha.challenge),
+        ("validate", &captcha.validate),
+        ("seccode", &captcha.seccode()),
+      ])
+      .send()
+      .await?
+      .json()
+      .await?;
+    if rsp.code != 0 {
+      return Err(anyhow!(
+        "Failed to login with password, code {}: {}",
+        rsp.code,
+        rsp.message.unwrap_or_default()
+      ));
+    }
+    match rsp.data {
+      Some(data) if data.status != 0 => Err(anyhow!(
+        "Further verification is required: {}, please verify at {} or login with SMS",
+        data.message.unwrap_or_default(),
+        data.url.unwrap_or_default()
+      )),
+      data => Ok(data.and_then(|i| i.refresh_token)),
+    }
+  }
+
   // endregion ======= Login ======= //
 
   // region ======= Info =======
@@ -144,15 +475,95 @@ impl BiliClient {
 
   // endregion ======= Info ======= //
 
+  // region ======= Album =======
+
+  /// Posts of `uid` in the album, newest first
+  async fn get_album_docs(&self, uid: &str, page: u32) -> Result<Vec<AlbumDoc>> {
+    let rsp: AlbumListRsp = self
+      .reqwest()
+      .get(SPACE_ALBUM_LIST_URL)
+      .query(&[
+        ("uid", uid),
+        ("page_num", &page.to_string()),
+        ("page_size", &ALBUM_PAGE_SIZE.to_string()),
+        ("biz", "all"),
+      ])
+      .send()
+      .await?
+      .json()
+      .await?;
+    match rsp.data {
+      Some(list) if rsp.code == 0 => Ok(list.items),
+      _ => Err(BiliApiError::from_code(rsp.code, rsp.message))?,
+    }
+  }
+
+  async fn get_album_doc(&self, doc_id: u64) -> Result<AlbumDoc> {
+    let rsp: AlbumDetailRsp = self
+      .reqwest()
+      .get(ALBUM_INFO_GET_URL)
+      .query(&[("doc_id", doc_id)])
+      .send()
+      .await?
+      .json()
+      .await?;
+    match rsp.data {
+      Some(detail) if rsp.code == 0 => Ok(detail.item),
+      _ => Err(BiliApiError::from_code(rsp.code, rsp.message))?,
+    }
+  }
+
+  /// Post a doc of one picture, returning its id
+  async fn create_album_doc(&self, picture: &str) -> Result<u64> {
+    let csrf = self.get_csrf().await?;
+    let rsp: AlbumCreateRsp = self
+      .reqwest()
+      .post(ALBUM_CREATE_URL)
+      .header(REFERER, FEED_DOMAIN)
+      .header(ORIGIN, FEED_DOMAIN)
+      .form(&album_doc_form(picture, &csrf))
+      .send()
+      .await?
+      .json()
+      .await?;
+    match rsp.data {
+      Some(created) if rsp.code == 0 => Ok(created.doc_id),
+      _ => Err(BiliApiError::from_code(rsp.code, rsp.message))?,
+    }
+  }
+
+  // endregion ======= Album ======= //
+
   // region ======= Upload =======
 
   async fn upload_image_via_album(
     &self,
     init_part: Part,
+  ) -> Result<AlbumUploadRsp, AlbumUploadError> {
+    self
+      .upload_image_to(ALBUM_UPLOAD_URL, "draw", init_part)
+      .await
+  }
+
+  /// Same response as the album, with the url under `bfs/new_dyn`
+  async fn upload_image_via_dynamic(
+    &self,
+    init_part: Part,
+  ) -> Result<AlbumUploadRsp, AlbumUploadError> {
+    self
+      .upload_image_to(DYNAMIC_UPLOAD_URL, "new_dyn", init_part)
+      .await
+  }
+
+  async fn upload_image_to(
+    &self,
+    url: &str,
+    biz: &str,
+    init_part: Part,
   ) -> Result<AlbumUploadRsp, AlbumUploadError> {
     let rsp = self
       .reqwest()
-      .post(ALBUM_UPLOAD_URL)
+      .post(url)
       .header(REFERER, FEED_DOMAIN)
       .header(ORIGIN, FEED_DOMAIN)
       .multipart(
@@ -164,7 +575,7 @@ impl BiliClient {
               .unwrap()
               .file_name("B站未来有可能会倒闭，但绝不会变质"),
           )
-          .text("biz", "draw")
+          .text("biz", biz.to_string())
           .text("category", "daily")
           .text("csrf", self.get_csrf().await?),
       )
@@ -174,6 +585,45 @@ impl BiliClient {
     Ok(rsp)
   }
 
+  /// Upload via the album, or dynamic if `dynamic`, login again once if the session is invalid
+  async fn upload_image_via(&self, dynamic: bool, data: Bytes) -> Result<Url> {
+    let upload = |data: Bytes| async move {
+      let len = data.len() as u64;
+      let part = Part::stream_with_length(throttle::body(data), len);
+      if dynamic {
+        self.upload_image_via_dynamic(part).await
+      } else {
+        self.upload_image_via_album(part).await
+      }
+    };
+    let mut rsp = upload(data.clone()).await?;
+    if rsp.code == NOT_LOGIN_CODE {
+      self
+        .refresh_cookies(true)
+        .await
+        .context("Session is invalid, please login again")?;
+      rsp = upload(data).await?;
+    }
+    if rsp.code != 0 {
+      debug!("Failed to upload: {rsp:#?}");
+      Err(BiliApiError::from_code(rsp.code, rsp.message.clone()))?;
+    }
+    if let Some(data) = &rsp.data {
+      if let Some(url) = &data.image_url {
+        Ok(url.parse()?)
+      } else {
+        Err(anyhow!("Url is none {:#?}", &rsp))
+      }
+    } else {
+      Err(anyhow!("Data is none {:#?}", &rsp))
+    }
+  }
+
+  fn album_throttled(&self) -> bool {
+    let until = self.album_throttled_until.lock().unwrap();
+    until.is_some_and(|until| until > time::Instant::now())
+  }
+
   // endregion ======= Upload ======= //
 }
 
@@ -187,12 +637,63 @@ impl Driver for BiliClient {
     false
   }
 
+  fn capabilities(&self) -> Capabilities {
+    // Not documented by bilibili, the album rejects files larger than 20 MiB
+    Capabilities {
+      max_file_bytes: Some(20 * 1024 * 1024),
+      formats: &[ImageFormat::Png, ImageFormat::Webp, ImageFormat::Jpeg],
+      ..Capabilities::default()
+    }
+  }
+
+  fn http_client(&self) -> Client {
+    self.reqwest()
+  }
+
   async fn is_login(&self) -> Result<bool, anyhow::Error> {
-    self
+    let is_login = || async {
+      self
+        .get_self_info()
+        .await
+        .map(|a| a.data.is_login)
+        .context("Failed to get is login")
+    };
+    if is_login().await? {
+      return Ok(true);
+    }
+    // The session may be revived by refreshing
+    match self.refresh_cookies(true).await {
+      Ok(true) => is_login().await,
+      Ok(false) => Ok(false),
+      Err(err) => {
+        warn!("{err:#}");
+        Ok(false)
+      }
+    }
+  }
+
+  async fn user_info(&self) -> Result<Option<UserInfo>, anyhow::Error> {
+    let info = self
       .get_self_info()
       .await
-      .map(|a| a.data.is_login)
-      .context("Failed to get is login")
+      .context("Failed to get self info")?
+      .data;
+    if !info.is_login {
+      return Ok(None);
+    }
+    Ok(Some(UserInfo {
+      name: info.username,
+      uid: info.mid.map(|i| i.to_string()),
+    }))
+  }
+
+  fn session_expires(&self) -> Option<DateTime<Utc>> {
+    let store = self.cookie.read().ok()?;
+    let session = store.get("bilibili.com", "/", "SESSDATA")?;
+    match session.expires {
+      CookieExpiration::AtUtc(time) => Utc.timestamp_opt(time.unix_timestamp(), 0).single(),
+      CookieExpiration::SessionEnd => None,
+    }
   }
 
   async fn print_self_info(&self) {
@@ -220,6 +721,19 @@ impl Driver for BiliClient {
   }
 
   async fn log_out(&self) -> Result<(), anyhow::Error> {
+    // Local cookies are cleared anyway, so a broken session can always be logged out
+    match self.log_out_remote().await {
+      Ok(rsp) if rsp.code == 0 => debug!("Session invalidated on server"),
+      Ok(rsp) => warn!(
+        "Failed to invalidate session on server, code {}: {}",
+        rsp.code,
+        rsp.message.unwrap_or_default()
+      ),
+      Err(LogOutError::Csrf(GetCsrfError::NotLogin())) => {
+        debug!("Not login, nothing to invalidate")
+      }
+      Err(err) => warn!("Failed to invalidate session on server: {err}"),
+    }
     {
       let mut cookie = self
         .cookie
@@ -228,10 +742,11 @@ impl Driver for BiliClient {
       cookie.clear();
     }
     self.save_cookies().await?;
+    self.save_refresh_token(None)?;
     Ok(())
   }
 
-  async fn qr_login(&self) -> Result<(), anyhow::Error> {
+  async fn qr_login(&self, options: &QrOptions) -> Result<(), anyhow::Error> {
     let qr = self
       .get_login_qr()
       .await
@@ -241,13 +756,9 @@ impl Driver for BiliClient {
       .as_ref()
       .and_then(|qr| qr.url.clone())
       .context("Failed to get login qr, url is none")?;
-    info!("Please open bilibili app, scan the qrcode below and confirm.");
-    if qr2term::print_qr(url).is_err() {
-      warn!("Failed to send qrcode via terminal");
-      warn!("Please visit the website, then open bilibili app, scan the qrcode and confirm.");
-      info!("")
-    }
-    let rsp: Result<(), _> = timeout(Duration::from_secs(120), async {
+    info!("Please open bilibili app, scan the qrcode and confirm.");
+    let _display = crate::qr::show(&url, options).await?;
+    let rsp: Result<(), _> = timeout(options.timeout, async {
       loop {
         debug!("Try to login in");
         let login = self.login_qrcode(&qr).await;
@@ -263,20 +774,92 @@ impl Driver for BiliClient {
     })
     .await;
     if rsp.is_err() {
-      return rsp.context("Long time (120 seconds) not login, timed out.");
+      return rsp.with_context(|| {
+        format!(
+          "Long time ({}) not login, timed out.",
+          humantime::format_duration(options.timeout)
+        )
+      });
     };
     tokio::join!(
       async {
         if let Err(err) = self.save_cookies().await {
           error!("Failed to save cookies: {err:?}");
         }
+        // No refresh token is given by QR login
+        if let Err(err) = self.save_refresh_token(None) {
+          error!("Failed to remove refresh token: {err:?}");
+        }
       },
       self.print_self_info(),
     );
     Ok(())
   }
 
+  async fn sms_login(&self) -> Result<(), anyhow::Error> {
+    let theme = ColorfulTheme::default();
+    let countries = self.get_countries().await.unwrap_or_else(|err| {
+      warn!("Failed to get calling codes, only +86 is available: {err:?}");
+      vec![Country {
+        id: 1,
+        name: "中国大陆".to_string(),
+        calling_code: "86".to_string(),
+      }]
+    });
+    let labels: Vec<String> = countries
+      .iter()
+      .map(|i| format!("+{} {}", i.calling_code, i.name))
+      .collect();
+    let selected = Select::with_theme(&theme)
+      .with_prompt("Calling code")
+      .items(&labels)
+      .default(0)
+      .interact()?;
+    let calling_code = &countries[selected].calling_code;
+    let tel: String = Input::with_theme(&theme)
+      .with_prompt("Phone number")
+      .validate_with(|i: &String| {
+        if !i.is_empty() && i.chars().all(|c| c.is_ascii_digit()) {
+          Ok(())
+        } else {
+          Err("Only digits are allowed")
+        }
+      })
+      .interact_text()?;
+
+    let captcha = self.solve_captcha(&theme).await?;
+    let captcha_key = self.send_sms(calling_code, &tel, &captcha).await?;
+    info!("SMS is sent to +{calling_code} {tel}");
+
+    let code: String = Input::with_theme(&theme)
+      .with_prompt("SMS code")
+      .interact_text()?;
+    let refresh_token = self
+      .login_sms(calling_code, &tel, code.trim(), &captcha_key)
+      .await?;
+    self.save_cookies().await?;
+    self.save_refresh_token(refresh_token.as_deref())?;
+    self.print_self_info().await;
+    Ok(())
+  }
+
+  async fn password_login(&self, username: &str, password: &str) -> Result<(), anyhow::Error> {
+    let captcha = self.solve_captcha(&ColorfulTheme::default()).await?;
+    // The salt expires soon, so get the key after the captcha is solved
+    let key = self.get_rsa_key().await.context("Failed to get RSA key")?;
+    let encrypted = encrypt_password(&key.key, &key.hash, password)?;
+    let refresh_token = self.login_password(username, &encrypted, &captcha).await?;
+    self.save_cookies().await?;
+    self.save_refresh_token(refresh_token.as_deref())?;
+    self.print_self_info().await;
+    Ok(())
+  }
+
+  /// `ac_time_value` from the local storage of the browser may be pasted along,
+  /// as the refresh token. Pasted cookies carry no expiry, so it stays unknown until
+  /// the server sets them again.
   async fn cookie_login(&self, cookie: &str) -> Result<(), anyhow::Error> {
+    let mut refresh_token = None;
     {
       let mut store = self
         .cookie
@@ -298,11 +881,13 @@ impl Driver for BiliClient {
         Ok(cookies)
       }
 
-      let mut cookies = cookie_from_header(&*raw_cookie, &url).context("Unable to parse cookie")?;
+      let mut cookies = cookie_from_header(&raw_cookie, &url).context("Unable to parse cookie")?;
       store.clear();
       for x in cookies.iter_mut() {
-        let time = OffsetDateTime::now_utc().add(Duration::new(60 * 24 * 60 * 365, 0));
-        x.expires = CookieExpiration::AtUtc(time);
+        if x.name() == "ac_time_value" {
+          refresh_token = Some(x.value().to_string());
+          continue;
+        }
         x.domain = CookieDomain::Suffix("bilibili.com".to_string());
         store
           .insert(x.to_owned(), &url)
@@ -311,58 +896,211 @@ impl Driver for BiliClient {
     }
     {
       self.save_cookies().await?;
+      self.save_refresh_token(refresh_token.as_deref())?;
     }
     Ok(())
   }
 
   async fn upload_image(&self, data: Bytes) -> Result<Url, anyhow::Error> {
     debug!("Uploading image, size {}...", data.len());
-    let rsp = self.upload_image_via_album(Part::stream(data)).await?;
-    if rsp.code != 0 {
-      return Err(anyhow!("Response json code != 0: {:#?}", rsp));
+    if let Err(err) = self.refresh_cookies(false).await {
+      warn!("Failed to refresh cookies: {err:#}");
     }
-    if let Some(data) = &rsp.data {
-      if let Some(url) = &data.image_url {
-        Ok(url.parse()?)
-      } else {
-        Err(anyhow!("Url is none {:#?}", &rsp))
+    match self.route {
+      BiliRoute::Album => self.upload_image_via(false, data).await,
+      BiliRoute::Dynamic => self.upload_image_via(true, data).await,
+      BiliRoute::Auto if self.album_throttled() => self.upload_image_via(true, data).await,
+      BiliRoute::Auto => {
+        let err = match self.upload_image_via(false, data.clone()).await {
+          Ok(url) => return Ok(url),
+          Err(err) => err,
+        };
+        match err.downcast_ref::<BiliApiError>() {
+          Some(BiliApiError::RateLimited { .. }) => {
+            warn!(
+              "Album is throttled, upload via dynamic for {}",
+              humantime::format_duration(RATE_LIMITED_WAIT)
+            );
+            *self.album_throttled_until.lock().unwrap() =
+              Some(time::Instant::now() + RATE_LIMITED_WAIT);
+          }
+          Some(BiliApiError::Rejected { .. }) => {
+            warn!("Image is rejected by album, upload via dynamic");
+          }
+          _ => return Err(err),
+        }
+        self.upload_image_via(true, data).await
       }
+    }
+  }
+
+  fn recovery_of(&self, err: &anyhow::Error) -> Recovery {
+    err
+      .downcast_ref::<BiliApiError>()
+      .map_or(Recovery::Retry, BiliApiError::recovery)
+  }
+
+  /// Post the index to the album, whichever route it was uploaded via
+  async fn publish_index(&self, url: &Url, data: &Bytes) -> Result<()> {
+    let (width, height) = png::Decoder::new(data.as_ref())
+      .read_info()
+      .context("Index is not a png image")?
+      .info()
+      .size();
+    let picture = serde_json::json!([{
+      "img_src": url.as_str(),
+      "img_width": width,
+      "img_height": height,
+      "img_size": data.len() as f64 / 1024.0,
+    }]);
+    let doc_id = self.create_album_doc(&picture.to_string()).await?;
+    debug!("Index is posted to album doc {doc_id}");
+    Ok(())
+  }
+
+  /// Pictures of album posts
+  ///
+  /// Bilibili lists no image by itself, uploads are found only if posted to the album,
+  /// see [Driver::publish_index].
+  async fn uploaded_images(&self, limit: Option<usize>) -> Result<Vec<String>> {
+    let limit = limit.unwrap_or(usize::MAX);
+    let uid = self
+      .user_info()
+      .await?
+      .and_then(|i| i.uid)
+      .ok_or(BiliApiError::NotLogin)?;
+    let mut images = Vec::new();
+    for page in 0.. {
+      if images.len() >= limit {
+        break;
+      }
+      let docs = self.get_album_docs(&uid, page).await?;
+      let last = docs.len() < ALBUM_PAGE_SIZE;
+      for doc in docs {
+        if images.len() >= limit {
+          break;
+        }
+        let doc = if doc.pictures.len() < doc.count {
+          self.get_album_doc(doc.doc_id).await?
+        } else {
+          doc
+        };
+        images.extend(doc.pictures.into_iter().map(|i| i.img_src));
+      }
+      if last {
+        break;
+      }
+    }
+    images.truncate(limit);
+    Ok(images)
+  }
+
+  /// Try each CDN mirror in turn, starting from the one in `url`
+  async fn download_image(&self, url: &str) -> Result<Bytes> {
+    let url = self.un_abbr_url(url).unwrap_or_else(|| url.to_string());
+    let mut last_err = anyhow!("Not a bilibili image url: {url}");
+    let urls = if self.mirrors.is_empty() {
+      mirror_urls(&url, &CDN_MIRRORS)
     } else {
-      Err(anyhow!("Data is none {:#?}", &rsp))
+      mirror_urls(&url, &self.mirrors)
+    };
+    for url in urls {
+      let rsp = async { self.reqwest().get(&url).send().await?.error_for_status() };
+      match rsp.await {
+        Ok(rsp) => match throttle::read_body(rsp).await {
+          Ok(data) => return Ok(data),
+          Err(err) => last_err = err.into(),
+        },
+        Err(err) => last_err = err.into(),
+      }
+      warn!("Failed to download {url}: {last_err}");
     }
+    Err(last_err)
   }
 
   fn check_can_parse(&self, url: &str) -> bool {
     SHORT_FORM.is_match(url) || LONG_FORM.is_match(url)
   }
 
+  /// `bili://[<bucket>/]<hex>[.<format>]`, the album bucket and png format are omitted,
+  /// thumbnail parameters are dropped
   fn abbr_url(&self, url: &str) -> Option<String> {
-    if let Some(caps) = LONG_FORM.captures(url) {
-      return Some(format!("bili://{}", &caps["hex"]));
+    let caps = LONG_FORM.captures(url)?;
+    let mut short = String::from("bili://");
+    if &caps["bucket"] != "album" {
+      short.push_str(&caps["bucket"]);
+      short.push('/');
+    }
+    short.push_str(&caps["hex"]);
+    if &caps["format"] != "png" {
+      short.push('.');
+      short.push_str(&caps["format"]);
     }
-    None
+    Some(short)
   }
 
   fn un_abbr_url(&self, url: &str) -> Option<String> {
-    if let Some(caps) = SHORT_FORM.captures(url) {
-      return Some(format!(
-        "https://i0.hdslb.com/bfs/album/{}.png",
-        &caps["hex"]
-      ));
-    }
-    None
+    let caps = SHORT_FORM.captures(url)?;
+    let bucket = caps.name("bucket").map_or("album", |i| i.as_str());
+    let format = caps.name("format").map_or("png", |i| i.as_str());
+    Some(format!(
+      "https://{}/bfs/{bucket}/{}.{format}",
+      CDN_MIRRORS[0], &caps["hex"]
+    ))
   }
 }
 
+/// Hosts serving the same `bfs` files
+const CDN_MIRRORS: [&str; 3] = ["i0.hdslb.com", "i1.hdslb.com", "i2.hdslb.com"];
+
+/// `url` on every mirror, its own host first, without thumbnail parameters
+fn mirror_urls<S: AsRef<str>>(url: &str, mirrors: &[S]) -> Vec<String> {
+  let caps = match LONG_FORM.captures(url) {
+    Some(caps) => caps,
+    None => return vec![],
+  };
+  let path = format!(
+    "bfs/{}/{}.{}",
+    &caps["bucket"], &caps["hex"], &caps["format"]
+  );
+  let host = &caps["host"];
+  std::iter::once(host)
+    .chain(mirrors.iter().map(AsRef::as_ref).filter(|i| *i != host))
+    .map(|host| format!("https://{host}/{path}"))
+    .collect()
+}
+
+/// Form of a daily album doc with `pictures` in json
+fn album_doc_form<'a>(pictures: &'a str, csrf: &'a str) -> [(&'static str, &'a str); 8] {
+  [
+    ("biz", "3"),
+    ("category", "3"),
+    ("type", "0"),
+    ("pictures", pictures),
+    ("description", "cutis index"),
+    ("content", "cutis index"),
+    ("csrf", csrf),
+    ("csrf_token", csrf),
+  ]
+}
+
 // regexes
 lazy_static! {
   static ref SHORT_FORM: Regex = Regex::new(
     r#"(?x)
     ^
     bili://
+    (?:
+      (?P<bucket>[a-z0-9_]+)/
+    )?
     (?P<hex>
       [a-f0-9]{40}
+      # dynamic images are named with the uid after the hash
+      | [a-f0-9]{32}[0-9]{1,20}
     )
+    (?:
+      \.(?P<format>png|jpe?g|webp|gif)
+    )?
     $
     "#
   )
@@ -370,14 +1108,27 @@ lazy_static! {
   static ref LONG_FORM: Regex = Regex::new(
     r#"(?x)
     ^
-    https?://i0\.hdslb\.com/bfs/album/
+    https?://
+    (?P<host>
+      i[0-2]\.hdslb\.com
+    )
+    /bfs/
+    (?P<bucket>
+      [a-z0-9_]+
+    )
+    /
     (?P<hex>
       [a-f0-9]{40}
+      | [a-f0-9]{32}[0-9]{1,20}
     )
     \.
     (?P<format>
-      png|jpeg
+      png|jpe?g|webp|gif
     )
+    # thumbnail parameters, e.g. `@100w_100h_1c.webp`
+    (?:
+      @[0-9a-z_.]*
+    )?
     $
     "#
   )
@@ -411,6 +1162,69 @@ pub enum GetCsrfError {
   NotLogin(),
 }
 
+/// Error codes of bilibili API responses
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum BiliApiError {
+  #[error("Not login or the session expired, please login again with `cutis login`")]
+  NotLogin,
+  #[error("Invalid csrf token, please login again with `cutis login`")]
+  Csrf,
+  #[error("Rate limited by bilibili, code {code}: {message}")]
+  RateLimited { code: i32, message: String },
+  #[error("File is too large for bilibili, try a smaller `--block-size`: {message}")]
+  FileTooLarge { message: String },
+  #[error("Image is rejected by bilibili, try another `--encoder`: {message}")]
+  Rejected { message: String },
+  #[error("Server error of bilibili, code {code}: {message}")]
+  Server { code: i32, message: String },
+  #[error("Unknown error of bilibili, code {code}: {message}")]
+  Other { code: i32, message: String },
+}
+
+/// Wait after bilibili rate limits requests
+const RATE_LIMITED_WAIT: Duration = Duration::from_secs(60);
+
+impl BiliApiError {
+  pub fn from_code(code: i32, message: Option<String>) -> BiliApiError {
+    let message = message.unwrap_or_default();
+    match code {
+      NOT_LOGIN_CODE => BiliApiError::NotLogin,
+      -111 => BiliApiError::Csrf,
+      // Intercepted, risk control, too frequent
+      -412 | -352 | -509 | -799 => BiliApiError::RateLimited { code, message },
+      -616 => BiliApiError::FileTooLarge { message },
+      -500 | -502 | -503 | -504 => BiliApiError::Server { code, message },
+      _ if message.contains("过大") || message.contains("too large") => {
+        BiliApiError::FileTooLarge { message }
+      }
+      _ if ["违规", "违禁", "审核", "敏感"]
+        .iter()
+        .any(|i| message.contains(i)) =>
+      {
+        BiliApiError::Rejected { message }
+      }
+      _ => BiliApiError::Other { code, message },
+    }
+  }
+
+  pub fn recovery(&self) -> Recovery {
+    match self {
+      BiliApiError::NotLogin | BiliApiError::Csrf => Recovery::Relogin,
+      BiliApiError::RateLimited { .. } => Recovery::Wait(RATE_LIMITED_WAIT),
+      BiliApiError::FileTooLarge { .. } | BiliApiError::Rejected { .. } => Recovery::Abort,
+      BiliApiError::Server { .. } | BiliApiError::Other { .. } => Recovery::Retry,
+    }
+  }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum LogOutError {
+  #[error("Invalid csrf")]
+  Csrf(#[from] GetCsrfError),
+  #[error("A network error occurred {0}")]
+  Network(#[from] reqwest::Error),
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum AlbumUploadError {
   #[error("Invalid csrf")]
@@ -428,7 +1242,9 @@ mod tests {
 
   use crate::encoder::Encoder;
   use crate::init_logger;
+  use crate::qr::QrOptions;
 
+  use super::album_doc_form;
   use super::BiliClient;
   use super::Driver;
   use super::GetCsrfError;
@@ -450,7 +1266,7 @@ mod tests {
       }
       Err(err) => match err {
         GetCsrfError::NotLogin() => println!("Not login!"),
-        _ => Err(err).unwrap(),
+        _ => panic!("{err:?}"),
       },
     };
   }
@@ -466,7 +1282,7 @@ mod tests {
       return;
     }
     init_logger(None);
-    TEST_CLI.qr_login().await.unwrap();
+    TEST_CLI.qr_login(&QrOptions::default()).await.unwrap();
     info!("Login successfully!");
   }
 
@@ -478,8 +1294,8 @@ mod tests {
 
   #[tokio::test]
   async fn upload_image_via_album_test() {
-    let enc = crate::encoder::png::PngEncoder();
-    let encoded = enc.encode(&[80; 114514]).await.unwrap();
+    let enc = crate::encoder::png::PngEncoder::default();
+    let encoded = enc.encode(&[80; 114514]).unwrap();
     let rsp = TEST_CLI
       .upload_image_via_album(Part::bytes(encoded))
       .await
@@ -493,10 +1309,76 @@ mod tests {
       return;
     }
     let cookie = std::env::var("BILI_COOKIE").unwrap();
-    TEST_CLI.cookie_login(&*cookie).await.unwrap();
+    TEST_CLI.cookie_login(&cookie).await.unwrap();
     dbg!(TEST_CLI.get_self_info().await.unwrap());
   }
 
+  #[tokio::test]
+  async fn pasted_cookies_have_unknown_expiry() {
+    let dir = std::env::temp_dir().join(format!("cutis-pasted-cookies-{}", std::process::id()));
+    let mut client = BiliClient::new_with_options("pasted", |i| i).await.unwrap();
+    client.cookie_path = dir.join("pasted.jsonl");
+    client
+      .cookie_login("SESSDATA=abc; bili_jct=def; ac_time_value=ghi")
+      .await
+      .unwrap();
+    assert_eq!(client.session_expires(), None);
+    assert_eq!(client.get_csrf().await.unwrap(), "def");
+
+    // Saved without an expiry and loaded back
+    let file = std::fs::File::open(&client.cookie_path).unwrap();
+    let store = super::CookieStore::load_json(std::io::BufReader::new(file)).unwrap();
+    let session = store.get("bilibili.com", "/", "SESSDATA").unwrap();
+    assert_eq!(session.value(), "abc");
+    assert!(!session.is_persistent());
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn api_error_codes() {
+    use super::{BiliApiError, Recovery};
+
+    let error = |code, message: &str| BiliApiError::from_code(code, Some(message.to_string()));
+    assert_eq!(error(-101, "账号未登录"), BiliApiError::NotLogin);
+    assert_eq!(error(-101, "").recovery(), Recovery::Relogin);
+    assert_eq!(error(-111, "csrf 校验失败").recovery(), Recovery::Relogin);
+    assert!(matches!(
+      error(-412, "请求被拦截").recovery(),
+      Recovery::Wait(_)
+    ));
+    assert!(matches!(
+      error(-4, "文件过大"),
+      BiliApiError::FileTooLarge { .. }
+    ));
+    assert_eq!(error(-4, "图片违规").recovery(), Recovery::Abort);
+    assert_eq!(error(-503, "服务调用超时").recovery(), Recovery::Retry);
+    assert_eq!(
+      BiliApiError::from_code(1, None),
+      BiliApiError::Other {
+        code: 1,
+        message: String::new()
+      }
+    );
+  }
+
+  #[test]
+  fn encrypt_password_test() {
+    use base64::Engine;
+    use rsa::pkcs8::{EncodePublicKey, LineEnding};
+    use rsa::{Pkcs1v15Encrypt, RsaPrivateKey};
+
+    let private = RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap();
+    let pem = private
+      .to_public_key()
+      .to_public_key_pem(LineEnding::LF)
+      .unwrap();
+    let encrypted = super::encrypt_password(&pem, "7a3b", "hunter2").unwrap();
+    let encrypted = super::BASE64.decode(encrypted).unwrap();
+    let decrypted = private.decrypt(Pkcs1v15Encrypt, &encrypted).unwrap();
+    assert_eq!(decrypted, b"7a3bhunter2");
+    assert!(super::encrypt_password("not a key", "", "").is_err());
+  }
+
   #[test]
   fn check_can_parse_test() {
     assert!(TEST_CLI.check_can_parse("bili://2569aaaa4f9b28787cf1f0c5b1134cc7e0900000"));
@@ -513,8 +1395,25 @@ mod tests {
       "https://i0.hdslb.com/bfs/album/2569aa4f9b28787cf1f0c5b1134cc7e0900000.webm"
     ));
     assert!(!TEST_CLI.check_can_parse("bili://FAILED"));
-    assert!(!TEST_CLI.check_can_parse("bili://2569aaaa4f9b28787cf1f0c5b1134cc7e0900000.png"));
+    assert!(TEST_CLI.check_can_parse("bili://2569aaaa4f9b28787cf1f0c5b1134cc7e0900000.png"));
+    assert!(!TEST_CLI.check_can_parse("bili://2569aaaa4f9b28787cf1f0c5b1134cc7e0900000.webm"));
     assert!(!TEST_CLI.check_can_parse("2569AAaa4f9b28787cf1f0c5b1134cc7e0900000"));
+    assert!(TEST_CLI.check_can_parse(
+      "http://i0.hdslb.com/bfs/new_dyn/4b1a9b7a8e0d2d6f1c3a2f2a4a3b0e5c1234567.png"
+    ));
+    assert!(TEST_CLI.check_can_parse("bili://new_dyn/4b1a9b7a8e0d2d6f1c3a2f2a4a3b0e5c1234567"));
+    assert!(TEST_CLI.check_can_parse(
+      "https://i2.hdslb.com/bfs/emote/2569aaaa4f9b28787cf1f0c5b1134cc7e0900000.jpg@100w_100h.webp"
+    ));
+    assert!(TEST_CLI.check_can_parse(
+      "https://i1.hdslb.com/bfs/article/2569aaaa4f9b28787cf1f0c5b1134cc7e0900000.webp"
+    ));
+    assert!(!TEST_CLI.check_can_parse(
+      "https://i3.hdslb.com/bfs/album/2569aaaa4f9b28787cf1f0c5b1134cc7e0900000.png"
+    ));
+    assert!(!TEST_CLI.check_can_parse(
+      "https://i0.hdslb.com/bfs/album/2569aaaa4f9b28787cf1f0c5b1134cc7e0900000.png/x"
+    ));
   }
 
   #[test]
@@ -534,5 +1433,61 @@ mod tests {
         .abbr_url("https://i0.hdslb.com/bfs/album/2569aaaa4f9b28787cf1f0c5b1134cc7e0900000.png"),
       Some("bili://2569aaaa4f9b28787cf1f0c5b1134cc7e0900000".to_string()),
     );
+    let dynamic = "https://i0.hdslb.com/bfs/new_dyn/4b1a9b7a8e0d2d6f1c3a2f2a4a3b0e5c1234567.png";
+    let abbr = TEST_CLI.abbr_url(dynamic).unwrap();
+    assert_eq!(
+      abbr,
+      "bili://new_dyn/4b1a9b7a8e0d2d6f1c3a2f2a4a3b0e5c1234567"
+    );
+    assert_eq!(TEST_CLI.un_abbr_url(&abbr).as_deref(), Some(dynamic));
+    // The extension is kept, thumbnail parameters and the mirror are dropped
+    let abbr = TEST_CLI
+      .abbr_url(
+        "http://i1.hdslb.com/bfs/article/2569aaaa4f9b28787cf1f0c5b1134cc7e0900000.jpg@1e_1c.webp",
+      )
+      .unwrap();
+    assert_eq!(
+      abbr,
+      "bili://article/2569aaaa4f9b28787cf1f0c5b1134cc7e0900000.jpg"
+    );
+    assert_eq!(
+      TEST_CLI.un_abbr_url(&abbr).as_deref(),
+      Some("https://i0.hdslb.com/bfs/article/2569aaaa4f9b28787cf1f0c5b1134cc7e0900000.jpg")
+    );
+  }
+
+  #[test]
+  fn album_doc_form_test() {
+    let form = album_doc_form(r#"[{"img_src":"a"}]"#, "jct");
+    let field = |name| form.iter().find(|(i, _)| *i == name).map(|(_, v)| *v);
+    assert_eq!(field("pictures"), Some(r#"[{"img_src":"a"}]"#));
+    assert_eq!(field("csrf"), Some("jct"));
+    assert_eq!(field("csrf_token"), Some("jct"));
+  }
+
+  #[test]
+  fn mirror_urls_test() {
+    assert_eq!(
+      super::mirror_urls(
+        "http://i1.hdslb.com/bfs/album/2569aaaa4f9b28787cf1f0c5b1134cc7e0900000.png@50w.webp",
+        &super::CDN_MIRRORS
+      ),
+      [
+        "https://i1.hdslb.com/bfs/album/2569aaaa4f9b28787cf1f0c5b1134cc7e0900000.png",
+        "https://i0.hdslb.com/bfs/album/2569aaaa4f9b28787cf1f0c5b1134cc7e0900000.png",
+        "https://i2.hdslb.com/bfs/album/2569aaaa4f9b28787cf1f0c5b1134cc7e0900000.png",
+      ]
+    );
+    assert_eq!(
+      super::mirror_urls(
+        "https://i0.hdslb.com/bfs/new_dyn/2569aaaa4f9b28787cf1f0c5b1134cc7e0900000.jpg",
+        &["i0.hdslb.com", "i9.hdslb.com"]
+      ),
+      [
+        "https://i0.hdslb.com/bfs/new_dyn/2569aaaa4f9b28787cf1f0c5b1134cc7e0900000.jpg",
+        "https://i9.hdslb.com/bfs/new_dyn/2569aaaa4f9b28787cf1f0c5b1134cc7e0900000.jpg",
+      ]
+    );
+    assert!(super::mirror_urls("https://example.com/a.png", &super::CDN_MIRRORS).is_empty());
   }
 }
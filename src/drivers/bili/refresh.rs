@@ -0,0 +1,237 @@
+//! Web cookie refresh, the server asks for it before the session is invalidated
+//!
+//! 1. Ask [COOKIE_INFO_URL] whether to refresh
+//! 2. Get `refresh_csrf` from [CORRESPOND_URL] with a path encrypted from the server time
+//! 3. Refresh with the `refresh_token` saved at login, then confirm with the old one
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use regex::Regex;
+use rsa::pkcs8::DecodePublicKey;
+use rsa::{Oaep, RsaPublicKey};
+use sha2::Sha256;
+use tokio::time::Instant;
+use tracing::{debug, info};
+
+use super::data::{ConfirmRefreshRsp, CookieInfoRsp, CookieRefreshRsp};
+use super::url::{CONFIRM_REFRESH_URL, COOKIE_INFO_URL, COOKIE_REFRESH_URL, CORRESPOND_URL};
+use super::BiliClient;
+use crate::drivers::Driver;
+
+/// Key to encrypt the correspond path, fixed by bilibili
+const CORRESPOND_KEY: &str = "-----BEGIN PUBLIC KEY-----
+MIGfMA0GCSqGSIb3DQEBAQUAA4GNADCBiQKBgQDLgd2OAkcGVtoE3ThUREbio0Eg
+Uc/prcajMKXvkCKFCWhJYJcLkcM2DKKcSeFpD/j6Boy538YXnR6VhcuUJOhH2x71
+nzPjfdTcqMz7djHum0qSZA0AyCBDABUqCrfNgCiJ00Ra7GmRj+YCK1NJEuewlb40
+JNrRuoEUXpabUzGB8QIDAQAB
+-----END PUBLIC KEY-----";
+
+/// Ask the server whether to refresh at most once in this interval
+const CHECK_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Refresh anyway if the session expires within this time
+const EXPIRY_MARGIN: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+lazy_static! {
+  static ref REFRESH_CSRF: Regex = Regex::new(r#"<div id="1-name">\s*([^<\s]+)\s*</div>"#).unwrap();
+}
+
+/// Encrypt `refresh_{timestamp}` with [CORRESPOND_KEY], as lowercase hex
+fn correspond_path(timestamp: u64) -> Result<String> {
+  let key = RsaPublicKey::from_public_key_pem(CORRESPOND_KEY)?;
+  let encrypted = key.encrypt(
+    &mut rand::thread_rng(),
+    Oaep::new::<Sha256>(),
+    format!("refresh_{timestamp}").as_bytes(),
+  )?;
+  Ok(encrypted.iter().map(|i| format!("{i:02x}")).collect())
+}
+
+fn parse_refresh_csrf(html: &str) -> Option<String> {
+  REFRESH_CSRF.captures(html).map(|caps| caps[1].to_string())
+}
+
+impl BiliClient {
+  fn refresh_token_path(&self) -> PathBuf {
+    self.cookie_path.with_extension("token")
+  }
+
+  fn load_refresh_token(&self) -> Option<String> {
+    let token = fs::read_to_string(self.refresh_token_path()).ok()?;
+    let token = token.trim();
+    (!token.is_empty()).then(|| token.to_string())
+  }
+
+  /// Save the token to refresh cookies of this login, or remove a stale one
+  pub(super) fn save_refresh_token(&self, token: Option<&str>) -> io::Result<()> {
+    let path = self.refresh_token_path();
+    match token {
+      Some(token) => {
+        if let Some(parent) = path.parent() {
+          fs::create_dir_all(parent)?;
+        }
+        fs::write(path, token)
+      }
+      None => match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+      },
+    }
+  }
+
+  fn expires_soon(&self) -> bool {
+    let margin = chrono::Duration::from_std(EXPIRY_MARGIN).unwrap();
+    self
+      .session_expires()
+      .is_some_and(|expires| expires - Utc::now() < margin)
+  }
+
+  /// Refresh cookies if the server asks for it or they expire soon
+  ///
+  /// Checked at most once per [CHECK_INTERVAL], unless `force`d, e.g. after the session is
+  /// rejected. Return whether cookies are refreshed.
+  pub(super) async fn refresh_cookies(&self, force: bool) -> Result<bool> {
+    let mut last_check = self.last_refresh_check.lock().await;
+    if !force && last_check.is_some_and(|i| i.elapsed() < CHECK_INTERVAL) {
+      return Ok(false);
+    }
+    let csrf = match self.get_csrf().await {
+      Ok(csrf) => csrf,
+      // Not login, nothing to refresh
+      Err(_) => return Ok(false),
+    };
+    // A failed check waits for the next interval as well
+    *last_check = Some(Instant::now());
+    let rsp: CookieInfoRsp = self
+      .reqwest()
+      .get(COOKIE_INFO_URL)
+      .query(&[("csrf", &csrf)])
+      .send()
+      .await?
+      .json()
+      .await?;
+    let cookie_info = match rsp.data {
+      Some(info) if rsp.code == 0 => info,
+      _ => Err(anyhow!(
+        "Session is invalid, code {}: {}",
+        rsp.code,
+        rsp.message.unwrap_or_default()
+      ))?,
+    };
+    if !cookie_info.refresh && !self.expires_soon() {
+      debug!("Cookies are fresh");
+      return Ok(false);
+    }
+    let refresh_token = self.load_refresh_token().context(
+      "Cookies need to be refreshed, but no refresh token is saved, \
+      please login again with --sms or --password",
+    )?;
+
+    let path = correspond_path(cookie_info.timestamp)?;
+    let html = self
+      .reqwest()
+      .get(format!("{CORRESPOND_URL}{path}"))
+      .send()
+      .await?
+      .text()
+      .await?;
+    let refresh_csrf = parse_refresh_csrf(&html).context("No refresh_csrf in correspond page")?;
+
+    let rsp: CookieRefreshRsp = self
+      .reqwest()
+      .post(COOKIE_REFRESH_URL)
+      .form(&[
+        ("csrf", csrf.as_str()),
+        ("refresh_csrf", &refresh_csrf),
+        ("source", "main_web"),
+        ("refresh_token", &refresh_token),
+      ])
+      .send()
+      .await?
+      .json()
+      .await?;
+    let new_token = match rsp.data.and_then(|i| i.refresh_token) {
+      Some(token) if rsp.code == 0 => token,
+      _ => Err(anyhow!(
+        "Failed to refresh cookies, code {}: {}, please login again",
+        rsp.code,
+        rsp.message.unwrap_or_default()
+      ))?,
+    };
+    self.save_cookies().await?;
+    self.save_refresh_token(Some(&new_token))?;
+
+    // Invalidate the old session with the new csrf
+    let csrf = self.get_csrf().await?;
+    let rsp: ConfirmRefreshRsp = self
+      .reqwest()
+      .post(CONFIRM_REFRESH_URL)
+      .form(&[("csrf", csrf.as_str()), ("refresh_token", &refresh_token)])
+      .send()
+      .await?
+      .json()
+      .await?;
+    if rsp.code != 0 {
+      debug!(
+        "Failed to confirm refresh, code {}: {}",
+        rsp.code,
+        rsp.message.unwrap_or_default()
+      );
+    }
+    info!("Cookies of bilibili are refreshed");
+    Ok(true)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{correspond_path, parse_refresh_csrf};
+  use crate::drivers::bili::BiliClient;
+
+  #[tokio::test]
+  async fn failed_check_waits_for_interval() {
+    // Nothing listens there, every request fails
+    let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let proxy = format!("http://{}", closed.local_addr().unwrap());
+    drop(closed);
+    let client = BiliClient::new_with_options("refresh-check", |i| {
+      i.proxy(reqwest::Proxy::all(proxy).unwrap())
+    })
+    .await
+    .unwrap();
+    {
+      let mut store = client.cookie.write().unwrap();
+      let url = "https://bilibili.com".parse().unwrap();
+      let cookie = cookie_store::Cookie::parse("bili_jct=abc; Domain=bilibili.com", &url).unwrap();
+      store.insert(cookie, &url).unwrap();
+    }
+    assert!(client.refresh_cookies(false).await.is_err());
+    assert!(!client.refresh_cookies(false).await.unwrap());
+  }
+
+  #[test]
+  fn correspond_path_is_hex() {
+    let path = correspond_path(1684466082062).unwrap();
+    // 1024 bits
+    assert_eq!(path.len(), 256);
+    assert!(path
+      .chars()
+      .all(|c| c.is_ascii_hexdigit() && !c.is_ascii_uppercase()));
+  }
+
+  #[test]
+  fn refresh_csrf_in_html() {
+    let html = r#"<html><body><div id="1-name">b0cc8411ded2f9db2cff2edb3123acac</div>
+      <div id="2-name"></div></body></html>"#;
+    assert_eq!(
+      parse_refresh_csrf(html).as_deref(),
+      Some("b0cc8411ded2f9db2cff2edb3123acac")
+    );
+    assert_eq!(parse_refresh_csrf("<div id=\"1-name\"></div>"), None);
+  }
+}
@@ -247,6 +247,8 @@ pub(super) const FAVORITES_MEDIA_GET_URL: &str = "https://api.bilibili.com/x/v3/
 
 // region =================== Album ========================
 
+pub(super) const ALBUM_CREATE_URL: &str = "https://api.vc.bilibili.com/link_draw/v1/doc/create";
+
 pub(super) const ALBUM_INFO_GET_URL: &str = "https://api.vc.bilibili.com/link_draw/v1/doc/detail";
 
 pub(super) const DYNAMIC_UPLOAD_URL: &str =
@@ -354,6 +356,18 @@ pub(super) const LOGIN_WEB_SMS_URL: &str =
 
 pub(super) const LOG_OUT_URL: &str = "https://passport.bilibili.com/login/exit/v2";
 
+pub(super) const COOKIE_INFO_URL: &str =
+  "https://passport.bilibili.com/x/passport-login/web/cookie/info";
+
+/** Followed by the correspond path */
+pub(super) const CORRESPOND_URL: &str = "https://www.bilibili.com/correspond/1/";
+
+pub(super) const COOKIE_REFRESH_URL: &str =
+  "https://passport.bilibili.com/x/passport-login/web/cookie/refresh";
+
+pub(super) const CONFIRM_REFRESH_URL: &str =
+  "https://passport.bilibili.com/x/passport-login/web/confirm/refresh";
+
 // endregion
 
 // region =================== Message ========================
@@ -1,24 +1,146 @@
+use std::time::Duration;
+
 use async_trait::async_trait;
 use bytes::Bytes;
+use chrono::{DateTime, Utc};
 use reqwest::Url;
 
+use crate::encoder::png::ImageLimits;
+use crate::qr::QrOptions;
+
 pub mod bili;
+pub mod pool;
+pub mod throttle;
+
+/// Image formats a [Driver] may accept
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+  Png,
+  Webp,
+  Jpeg,
+}
+
+/// What to do after a failed upload
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Recovery {
+  /// Transient, retry after the retry interval
+  Retry,
+  /// Rate limited, retry after waiting at least this long
+  Wait(Duration),
+  /// The session is invalid, nothing can be uploaded until login again
+  Relogin,
+  /// Retrying the same data won't help
+  Abort,
+}
+
+/// Account used when none is given
+pub const DEFAULT_ACCOUNT: &str = "default";
+
+/// Logged in user of a [Driver]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UserInfo {
+  pub name: Option<String>,
+  pub uid: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+  pub requests: u32,
+  pub per: Duration,
+}
+
+impl RateLimit {
+  /// Min interval between two requests
+  pub fn interval(&self) -> Duration {
+    self.per / self.requests.max(1)
+  }
+}
+
+/// What a [Driver] accepts, used to validate and configure uploads
+#[derive(Clone, Debug)]
+pub struct Capabilities {
+  /// Max size of one uploaded file, in bytes
+  pub max_file_bytes: Option<u64>,
+  pub image_limits: ImageLimits,
+  pub formats: &'static [ImageFormat],
+  /// Whether the host re-encodes uploads lossily, e.g. converts them to JPEG
+  pub recompress: bool,
+  pub rate_limit: Option<RateLimit>,
+}
+
+impl Default for Capabilities {
+  fn default() -> Self {
+    Capabilities {
+      max_file_bytes: None,
+      image_limits: ImageLimits::default(),
+      formats: &[ImageFormat::Png],
+      recompress: false,
+      rate_limit: None,
+    }
+  }
+}
 
 #[async_trait]
 pub trait Driver: Send + Sync {
   fn upload_need_login(&self) -> bool;
   fn download_need_login(&self) -> bool;
+
+  fn capabilities(&self) -> Capabilities {
+    Capabilities::default()
+  }
+  /// HTTP client of the driver, with its proxy and cookies
+  fn http_client(&self) -> reqwest::Client;
   async fn is_login(&self) -> anyhow::Result<bool>;
+  /// Current user, [None] if not login
+  async fn user_info(&self) -> anyhow::Result<Option<UserInfo>>;
+  /// When the saved login expires, [None] if unknown
+  fn session_expires(&self) -> Option<DateTime<Utc>> {
+    None
+  }
   async fn print_self_info(&self);
   async fn log_out(&self) -> anyhow::Result<()>;
-  async fn qr_login(&self) -> anyhow::Result<()>;
+  async fn qr_login(&self, options: &QrOptions) -> anyhow::Result<()>;
   async fn cookie_login(&self, cookie: &str) -> anyhow::Result<()>;
+  /// Login with username and password, prompting for captchas in the terminal
+  async fn password_login(&self, _username: &str, _password: &str) -> anyhow::Result<()> {
+    Err(anyhow::anyhow!(
+      "Password login is not supported by this driver"
+    ))
+  }
+  /// Login with a code sent to a phone, prompting in the terminal
+  async fn sms_login(&self) -> anyhow::Result<()> {
+    Err(anyhow::anyhow!("SMS login is not supported by this driver"))
+  }
 
   async fn upload_image(&self, data: Bytes) -> anyhow::Result<Url>;
-  // async fn download_image(&self, url: Url) -> anyhow::Result<Vec<u8>> {
-  //   let bytes = reqwest::get(url).await?.bytes().await?;
-  //   anyhow::Ok(Vec::from(bytes))
-  // }
+  /// How to recover from an error of [Driver::upload_image], [Recovery::Retry] if unknown
+  fn recovery_of(&self, _err: &anyhow::Error) -> Recovery {
+    Recovery::Retry
+  }
+  /// Post an uploaded index image where [Driver::uploaded_images] can list it
+  async fn publish_index(&self, _url: &Url, _data: &Bytes) -> anyhow::Result<()> {
+    Err(anyhow::anyhow!(
+      "Publishing indexes is not supported by this driver"
+    ))
+  }
+  /// Urls of images uploaded by the current user that the driver can list, newest first,
+  /// at most `limit` of them
+  async fn uploaded_images(&self, _limit: Option<usize>) -> anyhow::Result<Vec<String>> {
+    Err(anyhow::anyhow!(
+      "Listing uploaded images is not supported by this driver"
+    ))
+  }
+  /// Download an image by its url in normal or short form
+  async fn download_image(&self, url: &str) -> anyhow::Result<Bytes> {
+    let url = self.un_abbr_url(url).unwrap_or_else(|| url.to_string());
+    let rsp = self
+      .http_client()
+      .get(url)
+      .send()
+      .await?
+      .error_for_status()?;
+    Ok(throttle::read_body(rsp).await?)
+  }
 
   /// Check a [url] can or not be parsed by this [Driver]
   fn check_can_parse(&self, _url: &str) -> bool {
@@ -43,3 +165,81 @@ pub trait Driver: Send + Sync {
     None
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use async_trait::async_trait;
+  use bytes::Bytes;
+  use reqwest::{Proxy, Url};
+  use tokio::io::{AsyncReadExt, AsyncWriteExt};
+  use tokio::net::TcpListener;
+
+  use crate::drivers::{Driver, UserInfo};
+  use crate::qr::QrOptions;
+
+  /// Driver with nothing but a client
+  struct ClientDriver(reqwest::Client);
+
+  #[async_trait]
+  impl Driver for ClientDriver {
+    fn upload_need_login(&self) -> bool {
+      false
+    }
+    fn download_need_login(&self) -> bool {
+      false
+    }
+    fn http_client(&self) -> reqwest::Client {
+      self.0.clone()
+    }
+    async fn is_login(&self) -> anyhow::Result<bool> {
+      Ok(false)
+    }
+    async fn user_info(&self) -> anyhow::Result<Option<UserInfo>> {
+      Ok(None)
+    }
+    async fn print_self_info(&self) {}
+    async fn log_out(&self) -> anyhow::Result<()> {
+      Ok(())
+    }
+    async fn qr_login(&self, _options: &QrOptions) -> anyhow::Result<()> {
+      Ok(())
+    }
+    async fn cookie_login(&self, _cookie: &str) -> anyhow::Result<()> {
+      Ok(())
+    }
+    async fn upload_image(&self, _data: Bytes) -> anyhow::Result<Url> {
+      Err(anyhow::anyhow!("Not supported"))
+    }
+  }
+
+  #[tokio::test]
+  async fn download_through_driver_client() {
+    let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = proxy.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+      let (mut stream, _) = proxy.accept().await.unwrap();
+      let mut buf = [0u8; 1024];
+      let len = stream.read(&mut buf).await.unwrap();
+      let request = String::from_utf8_lossy(&buf[..len]).to_string();
+      stream
+        .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\nimage")
+        .await
+        .unwrap();
+      request
+    });
+    let client = reqwest::Client::builder()
+      .proxy(Proxy::http(format!("http://{addr}")).unwrap())
+      .build()
+      .unwrap();
+    let data = ClientDriver(client)
+      .download_image("http://images.invalid/a.png")
+      .await
+      .unwrap();
+    assert_eq!(data, "image");
+    let request = server.await.unwrap();
+    assert!(
+      request.starts_with("GET http://images.invalid/a.png "),
+      "{request}"
+    );
+  }
+}
@@ -0,0 +1,298 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use reqwest::Url;
+use tokio::time::Instant;
+use tracing::{debug, warn};
+
+use super::{Capabilities, Driver, Recovery, UserInfo};
+use crate::qr::QrOptions;
+
+/// Upload failed with one account of a pool with others to take over
+#[derive(thiserror::Error, Debug)]
+#[error("Failed to upload with account {0}")]
+pub struct AccountFailed(pub String);
+
+/// Sideline time after the first failure, doubled on each consecutive one
+const SIDELINE_BASE: Duration = Duration::from_secs(30);
+const SIDELINE_MAX: Duration = Duration::from_secs(10 * 60);
+
+/// [Driver] spreading uploads over several accounts of one driver
+///
+/// Each upload goes to the least loaded account, ties are broken round-robin.
+/// An account whose upload fails, e.g. rate limited or logged out, is sidelined for a while.
+pub struct AccountPool {
+  accounts: Vec<(String, Box<dyn Driver + Send + Sync>)>,
+  state: Mutex<State>,
+}
+
+struct State {
+  next: usize,
+  slots: Vec<Slot>,
+}
+
+#[derive(Default)]
+struct Slot {
+  in_flight: usize,
+  failures: u32,
+  sidelined_until: Option<Instant>,
+}
+
+impl State {
+  fn new(len: usize) -> State {
+    State {
+      next: 0,
+      slots: (0..len).map(|_| Slot::default()).collect(),
+    }
+  }
+
+  fn is_available(slot: &Slot, now: Instant) -> bool {
+    slot.sidelined_until.is_none_or(|until| until <= now)
+  }
+
+  /// Pick an account and count it in flight, with when it's back if all are sidelined
+  fn pick(&mut self, now: Instant) -> (usize, Option<Instant>) {
+    let len = self.slots.len();
+    let order = (0..len).map(|i| (self.next + i) % len);
+    let picked = if self.slots.iter().any(|i| State::is_available(i, now)) {
+      order
+        .filter(|i| State::is_available(&self.slots[*i], now))
+        .min_by_key(|i| self.slots[*i].in_flight)
+    } else {
+      // All sidelined, wait for the one back soonest
+      order.min_by_key(|i| self.slots[*i].sidelined_until)
+    }
+    .unwrap_or(0);
+    self.next = (picked + 1) % len;
+    let slot = &mut self.slots[picked];
+    slot.in_flight += 1;
+    (picked, slot.sidelined_until.filter(|until| *until > now))
+  }
+
+  /// Mark an upload of account `index` finished, return the sideline time if it failed
+  fn finish(&mut self, index: usize, success: bool, now: Instant) -> Option<Duration> {
+    let slot = &mut self.slots[index];
+    slot.in_flight -= 1;
+    if success {
+      slot.failures = 0;
+      slot.sidelined_until = None;
+      return None;
+    }
+    slot.failures += 1;
+    let sideline = SIDELINE_BASE
+      .saturating_mul(1 << (slot.failures - 1).min(16))
+      .min(SIDELINE_MAX);
+    slot.sidelined_until = Some(now + sideline);
+    Some(sideline)
+  }
+}
+
+impl AccountPool {
+  /// `accounts` must not be empty
+  pub fn new(accounts: Vec<(String, Box<dyn Driver + Send + Sync>)>) -> AccountPool {
+    assert!(!accounts.is_empty(), "No account in pool");
+    let state = Mutex::new(State::new(accounts.len()));
+    AccountPool { accounts, state }
+  }
+
+  fn first(&self) -> &(dyn Driver + Send + Sync) {
+    self.accounts[0].1.as_ref()
+  }
+}
+
+/// `recovery` of `err` from an account, another account may take over if it's logged out
+fn take_over(recovery: Recovery, err: &anyhow::Error) -> Recovery {
+  if recovery == Recovery::Relogin && err.downcast_ref::<AccountFailed>().is_some() {
+    return Recovery::Retry;
+  }
+  recovery
+}
+
+#[async_trait]
+impl Driver for AccountPool {
+  fn upload_need_login(&self) -> bool {
+    self.first().upload_need_login()
+  }
+
+  fn download_need_login(&self) -> bool {
+    self.first().download_need_login()
+  }
+
+  fn capabilities(&self) -> Capabilities {
+    self.first().capabilities()
+  }
+
+  fn http_client(&self) -> reqwest::Client {
+    self.first().http_client()
+  }
+
+  async fn is_login(&self) -> anyhow::Result<bool> {
+    for (_, driver) in &self.accounts {
+      if !driver.is_login().await? {
+        return Ok(false);
+      }
+    }
+    Ok(true)
+  }
+
+  async fn user_info(&self) -> anyhow::Result<Option<UserInfo>> {
+    self.first().user_info().await
+  }
+
+  /// The earliest expiry of all accounts
+  fn session_expires(&self) -> Option<DateTime<Utc>> {
+    self
+      .accounts
+      .iter()
+      .filter_map(|(_, driver)| driver.session_expires())
+      .min()
+  }
+
+  async fn print_self_info(&self) {
+    for (_, driver) in &self.accounts {
+      driver.print_self_info().await
+    }
+  }
+
+  async fn log_out(&self) -> anyhow::Result<()> {
+    self.first().log_out().await
+  }
+
+  async fn qr_login(&self, options: &QrOptions) -> anyhow::Result<()> {
+    self.first().qr_login(options).await
+  }
+
+  async fn cookie_login(&self, cookie: &str) -> anyhow::Result<()> {
+    self.first().cookie_login(cookie).await
+  }
+
+  async fn sms_login(&self) -> anyhow::Result<()> {
+    self.first().sms_login().await
+  }
+
+  async fn password_login(&self, username: &str, password: &str) -> anyhow::Result<()> {
+    self.first().password_login(username, password).await
+  }
+
+  async fn upload_image(&self, data: Bytes) -> anyhow::Result<Url> {
+    let (index, back) = self.state.lock().unwrap().pick(Instant::now());
+    let (name, driver) = &self.accounts[index];
+    if let Some(back) = back {
+      debug!("All accounts are sidelined, wait for account {name}");
+      tokio::time::sleep_until(back).await;
+    }
+    debug!("Uploading with account {name}");
+    let result = driver.upload_image(data).await;
+    let sideline = self
+      .state
+      .lock()
+      .unwrap()
+      .finish(index, result.is_ok(), Instant::now());
+    if let Some(sideline) = sideline {
+      warn!(
+        "Account {name} failed to upload, sidelined for {}",
+        humantime::format_duration(sideline)
+      );
+    }
+    if self.accounts.len() > 1 {
+      result.map_err(|err| err.context(AccountFailed(name.clone())))
+    } else {
+      result.with_context(|| format!("Failed to upload with account {name}"))
+    }
+  }
+
+  /// Accounts are of the same driver, any of them classifies the error
+  fn recovery_of(&self, err: &anyhow::Error) -> Recovery {
+    take_over(self.first().recovery_of(err), err)
+  }
+
+  async fn publish_index(&self, url: &Url, data: &Bytes) -> anyhow::Result<()> {
+    self.first().publish_index(url, data).await
+  }
+
+  async fn uploaded_images(&self, limit: Option<usize>) -> anyhow::Result<Vec<String>> {
+    self.first().uploaded_images(limit).await
+  }
+
+  async fn download_image(&self, url: &str) -> anyhow::Result<Bytes> {
+    self.first().download_image(url).await
+  }
+
+  fn check_can_parse(&self, url: &str) -> bool {
+    self.first().check_can_parse(url)
+  }
+
+  fn abbr_url(&self, url: &str) -> Option<String> {
+    self.first().abbr_url(url)
+  }
+
+  fn un_abbr_url(&self, url: &str) -> Option<String> {
+    self.first().un_abbr_url(url)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use tokio::time::Instant;
+
+  use crate::drivers::pool::{take_over, AccountFailed, State};
+  use crate::drivers::Recovery;
+
+  #[test]
+  fn round_robin_least_loaded() {
+    let now = Instant::now();
+    let mut state = State::new(3);
+    assert_eq!(state.pick(now).0, 0);
+    assert_eq!(state.pick(now).0, 1);
+    assert_eq!(state.pick(now).0, 2);
+    state.finish(1, true, now);
+    // Account 1 has the least uploads in flight
+    assert_eq!(state.pick(now).0, 1);
+    state.finish(0, true, now);
+    state.finish(2, true, now);
+    assert_eq!(state.pick(now).0, 2);
+    assert_eq!(state.pick(now).0, 0);
+  }
+
+  #[test]
+  fn sideline_failing_accounts() {
+    let now = Instant::now();
+    let mut state = State::new(2);
+    assert_eq!(state.pick(now).0, 0);
+    assert_eq!(state.finish(0, false, now), Some(Duration::from_secs(30)));
+    // Only account 1 is available
+    for _ in 0..3 {
+      let picked = state.pick(now).0;
+      assert_eq!(picked, 1);
+      state.finish(picked, true, now);
+    }
+    let later = now + Duration::from_secs(30);
+    assert_eq!(state.pick(later), (0, None));
+    assert_eq!(state.finish(0, false, later), Some(Duration::from_secs(60)));
+    assert_eq!(state.pick(later), (1, None));
+    assert!(state.finish(1, false, later).is_some());
+    // All sidelined, the one back soonest is picked once it's back
+    let back = later + Duration::from_secs(30);
+    assert_eq!(state.pick(later), (1, Some(back)));
+    state.finish(1, true, back);
+    assert_eq!(state.pick(back), (1, None));
+  }
+
+  #[test]
+  fn other_accounts_take_over() {
+    let err = anyhow::anyhow!("Not login").context("Failed to upload block 0");
+    assert_eq!(take_over(Recovery::Relogin, &err), Recovery::Relogin);
+    let err = err.context(AccountFailed("alt".to_string()));
+    assert_eq!(take_over(Recovery::Relogin, &err), Recovery::Retry);
+    // Failures of the data or the driver are not helped by another account
+    assert_eq!(take_over(Recovery::Abort, &err), Recovery::Abort);
+    let wait = Recovery::Wait(Duration::from_secs(60));
+    assert_eq!(take_over(wait, &err), wait);
+  }
+}
@@ -0,0 +1,342 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use chrono::{DateTime, Local, NaiveTime, Utc};
+use futures::StreamExt;
+use reqwest::{Body, Response, Url};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tokio::time::Instant;
+
+use super::{Capabilities, Driver, Recovery, UserInfo};
+use crate::qr::QrOptions;
+
+/// Bytes debited from the limiter at once, bodies are sent and read in chunks of this size
+const CHUNK_SIZE: usize = 64 * 1024;
+
+tokio::task_local! {
+  /// Limiter of the [Throttled] call in progress, picked up by [body] and [read_body]
+  static LIMITER: Arc<RateLimiter>;
+}
+
+/// Body sending `data`, throttled chunk by chunk within a [Throttled] call
+pub fn body(data: Bytes) -> Body {
+  let limiter = match LIMITER.try_with(Arc::clone) {
+    Ok(limiter) => limiter,
+    Err(_) => return data.into(),
+  };
+  let chunks = (0..data.len())
+    .step_by(CHUNK_SIZE)
+    .map(move |i| data.slice(i..(i + CHUNK_SIZE).min(data.len())));
+  let stream = futures::stream::iter(chunks).then(move |chunk| {
+    let limiter = Arc::clone(&limiter);
+    async move {
+      limiter.acquire(chunk.len() as u64).await;
+      Ok::<_, std::io::Error>(chunk)
+    }
+  });
+  Body::wrap_stream(stream)
+}
+
+/// Read the body of `rsp`, throttled chunk by chunk within a [Throttled] call
+pub async fn read_body(rsp: Response) -> reqwest::Result<Bytes> {
+  let limiter = match LIMITER.try_with(Arc::clone) {
+    Ok(limiter) => limiter,
+    Err(_) => return rsp.bytes().await,
+  };
+  let mut data = BytesMut::new();
+  let mut stream = rsp.bytes_stream();
+  while let Some(chunk) = stream.next().await {
+    let chunk = chunk?;
+    for part in chunk.chunks(CHUNK_SIZE) {
+      limiter.acquire(part.len() as u64).await;
+    }
+    data.extend_from_slice(&chunk);
+  }
+  Ok(data.freeze())
+}
+
+/// Token bucket shared by all transfers, refilled by `rate` bytes per second
+///
+/// Each chunk takes its tokens before it's sent and the bucket may go into debt,
+/// later chunks wait for the debt of earlier ones, so the speed follows the rate.
+pub struct RateLimiter {
+  rate: u64,
+  schedule: Option<LimitSchedule>,
+  bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+  /// Negative if in debt
+  tokens: f64,
+  last: Instant,
+}
+
+impl Bucket {
+  /// Take `bytes` tokens at `now`, return how long to wait until the debt is paid
+  fn take(&mut self, now: Instant, bytes: u64, rate: u64) -> Duration {
+    let rate = rate as f64;
+    let refilled = now.saturating_duration_since(self.last).as_secs_f64() * rate;
+    // At most one second of burst
+    self.tokens = (self.tokens + refilled).min(rate);
+    self.last = now;
+    self.tokens -= bytes as f64;
+    if self.tokens < 0.0 {
+      Duration::from_secs_f64(-self.tokens / rate)
+    } else {
+      Duration::ZERO
+    }
+  }
+}
+
+impl RateLimiter {
+  pub fn new(rate: u64, schedule: Option<LimitSchedule>) -> RateLimiter {
+    RateLimiter {
+      rate: rate.max(1),
+      schedule,
+      bucket: Mutex::new(Bucket {
+        tokens: rate as f64,
+        last: Instant::now(),
+      }),
+    }
+  }
+
+  /// Wait until `bytes` can be transferred
+  pub async fn acquire(&self, bytes: u64) {
+    if let Some(schedule) = &self.schedule {
+      if !schedule.contains(Local::now().time()) {
+        return;
+      }
+    }
+    // Tokens are taken in order, then each waits for its own share without the lock
+    let wait = self
+      .bucket
+      .lock()
+      .unwrap()
+      .take(Instant::now(), bytes, self.rate);
+    if !wait.is_zero() {
+      tokio::time::sleep(wait).await;
+    }
+  }
+}
+
+/// Daily local time window, may wrap over midnight, e.g. `08:00-20:00` or `22:00-06:00`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LimitSchedule {
+  start: NaiveTime,
+  end: NaiveTime,
+}
+
+impl LimitSchedule {
+  pub fn contains(&self, time: NaiveTime) -> bool {
+    if self.start <= self.end {
+      self.start <= time && time < self.end
+    } else {
+      time >= self.start || time < self.end
+    }
+  }
+}
+
+impl FromStr for LimitSchedule {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let syntax = || format!("Invalid time window `{s}`, syntax: HH:MM-HH:MM");
+    let (start, end) = s.trim().split_once('-').ok_or_else(syntax)?;
+    let parse = |time: &str| NaiveTime::parse_from_str(time.trim(), "%H:%M").map_err(|_| syntax());
+    Ok(LimitSchedule {
+      start: parse(start)?,
+      end: parse(end)?,
+    })
+  }
+}
+
+impl Display for LimitSchedule {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "{}-{}",
+      self.start.format("%H:%M"),
+      self.end.format("%H:%M")
+    )
+  }
+}
+
+impl Serialize for LimitSchedule {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(self)
+  }
+}
+
+impl<'de> Deserialize<'de> for LimitSchedule {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    String::deserialize(deserializer)?
+      .parse()
+      .map_err(serde::de::Error::custom)
+  }
+}
+
+/// [Driver] whose transfers are limited by a shared [RateLimiter]
+///
+/// Only bodies sent with [body] and read with [read_body] by the inner driver are limited.
+pub struct Throttled {
+  inner: Box<dyn Driver + Send + Sync>,
+  limiter: Arc<RateLimiter>,
+}
+
+impl Throttled {
+  pub fn new(inner: Box<dyn Driver + Send + Sync>, limiter: Arc<RateLimiter>) -> Throttled {
+    Throttled { inner, limiter }
+  }
+}
+
+#[async_trait]
+impl Driver for Throttled {
+  fn upload_need_login(&self) -> bool {
+    self.inner.upload_need_login()
+  }
+
+  fn download_need_login(&self) -> bool {
+    self.inner.download_need_login()
+  }
+
+  fn capabilities(&self) -> Capabilities {
+    self.inner.capabilities()
+  }
+
+  fn http_client(&self) -> reqwest::Client {
+    self.inner.http_client()
+  }
+
+  async fn is_login(&self) -> anyhow::Result<bool> {
+    self.inner.is_login().await
+  }
+
+  async fn user_info(&self) -> anyhow::Result<Option<UserInfo>> {
+    self.inner.user_info().await
+  }
+
+  fn session_expires(&self) -> Option<DateTime<Utc>> {
+    self.inner.session_expires()
+  }
+
+  async fn print_self_info(&self) {
+    self.inner.print_self_info().await
+  }
+
+  async fn log_out(&self) -> anyhow::Result<()> {
+    self.inner.log_out().await
+  }
+
+  async fn qr_login(&self, options: &QrOptions) -> anyhow::Result<()> {
+    self.inner.qr_login(options).await
+  }
+
+  async fn cookie_login(&self, cookie: &str) -> anyhow::Result<()> {
+    self.inner.cookie_login(cookie).await
+  }
+
+  async fn sms_login(&self) -> anyhow::Result<()> {
+    self.inner.sms_login().await
+  }
+
+  async fn password_login(&self, username: &str, password: &str) -> anyhow::Result<()> {
+    self.inner.password_login(username, password).await
+  }
+
+  async fn upload_image(&self, data: Bytes) -> anyhow::Result<Url> {
+    let limiter = Arc::clone(&self.limiter);
+    LIMITER.scope(limiter, self.inner.upload_image(data)).await
+  }
+
+  fn recovery_of(&self, err: &anyhow::Error) -> Recovery {
+    self.inner.recovery_of(err)
+  }
+
+  async fn publish_index(&self, url: &Url, data: &Bytes) -> anyhow::Result<()> {
+    self.inner.publish_index(url, data).await
+  }
+
+  async fn uploaded_images(&self, limit: Option<usize>) -> anyhow::Result<Vec<String>> {
+    self.inner.uploaded_images(limit).await
+  }
+
+  async fn download_image(&self, url: &str) -> anyhow::Result<Bytes> {
+    let limiter = Arc::clone(&self.limiter);
+    LIMITER.scope(limiter, self.inner.download_image(url)).await
+  }
+
+  fn check_can_parse(&self, url: &str) -> bool {
+    self.inner.check_can_parse(url)
+  }
+
+  fn abbr_url(&self, url: &str) -> Option<String> {
+    self.inner.abbr_url(url)
+  }
+
+  fn un_abbr_url(&self, url: &str) -> Option<String> {
+    self.inner.un_abbr_url(url)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use chrono::NaiveTime;
+  use tokio::time::Instant;
+
+  use std::sync::Arc;
+
+  use bytes::Bytes;
+
+  use crate::drivers::throttle::{body, Bucket, LimitSchedule, RateLimiter, LIMITER};
+
+  #[test]
+  fn bucket_goes_into_debt() {
+    let start = Instant::now();
+    let mut bucket = Bucket {
+      tokens: 1000.0,
+      last: start,
+    };
+    assert_eq!(bucket.take(start, 500, 1000), Duration::ZERO);
+    assert_eq!(bucket.take(start, 2500, 1000), Duration::from_secs(2));
+    // Debt is paid after 2 seconds
+    let later = start + Duration::from_secs(2);
+    assert_eq!(bucket.take(later, 1000, 1000), Duration::from_secs(1));
+    // Burst is capped to one second
+    let much_later = later + Duration::from_secs(100);
+    assert_eq!(bucket.take(much_later, 1000, 1000), Duration::ZERO);
+    assert_eq!(bucket.take(much_later, 1000, 1000), Duration::from_secs(1));
+  }
+
+  #[tokio::test]
+  async fn body_is_throttled_in_scope() {
+    let data = Bytes::from(vec![0u8; 200 * 1024]);
+    // Sent at once outside of a throttled call
+    assert!(body(data.clone()).as_bytes().is_some());
+    let limiter = Arc::new(RateLimiter::new(1024, None));
+    let throttled = LIMITER.scope(limiter, async { body(data) }).await;
+    assert!(throttled.as_bytes().is_none());
+  }
+
+  #[test]
+  fn schedule_window() {
+    let time = |h, m| NaiveTime::from_hms_opt(h, m, 0).unwrap();
+    let day: LimitSchedule = "08:00-20:00".parse().unwrap();
+    assert!(day.contains(time(8, 0)));
+    assert!(day.contains(time(19, 59)));
+    assert!(!day.contains(time(20, 0)));
+    assert!(!day.contains(time(3, 0)));
+    let night: LimitSchedule = "22:30 - 06:00".parse().unwrap();
+    assert!(night.contains(time(23, 0)));
+    assert!(night.contains(time(5, 59)));
+    assert!(!night.contains(time(12, 0)));
+    assert_eq!(night.to_string(), "22:30-06:00");
+    assert!("8-20".parse::<LimitSchedule>().is_err());
+    assert!("25:00-06:00".parse::<LimitSchedule>().is_err());
+  }
+}
@@ -0,0 +1,176 @@
+use std::io::Cursor;
+
+use png::{BitDepth, ColorType, Compression, DecodingError, EncodingError};
+
+use super::{raw_bytes_within, Encoder};
+
+/// Every pixel is stored as 8-bit RGBA
+const BYTES_PER_PIXEL: usize = 4;
+
+/// Encoder spreading data across frames of an animated PNG
+///
+/// All frames share the same dimensions, the last frame is padded with zeros.
+pub struct ApngEncoder {
+  /// Max side length of a frame, in pixels
+  frame_side: u32,
+  /// Max number of frames in one image
+  max_frames: u32,
+}
+
+impl Default for ApngEncoder {
+  fn default() -> Self {
+    ApngEncoder {
+      // 4 MiB per frame
+      frame_side: 1024,
+      max_frames: 64,
+    }
+  }
+}
+
+impl ApngEncoder {
+  fn frame_capacity(&self) -> usize {
+    self.frame_side as usize * self.frame_side as usize * BYTES_PER_PIXEL
+  }
+
+  /// Compute `(frames, width, height)` which can hold `data_len` bytes
+  ///
+  /// # Return
+  ///
+  /// Return [None] if `data_len` needs more than `max_frames` frames
+  fn geometry(&self, data_len: usize) -> Option<(u32, u32, u32)> {
+    let data_len = data_len.max(1);
+    let frames = data_len.div_ceil(self.frame_capacity());
+    if frames > self.max_frames as usize {
+      return None;
+    }
+    let pixels = data_len.div_ceil(frames).div_ceil(BYTES_PER_PIXEL);
+    let width = ((pixels as f64).sqrt().ceil() as usize).min(self.frame_side as usize);
+    let height = pixels.div_ceil(width);
+    Some((frames as u32, width as u32, height as u32))
+  }
+}
+
+impl Encoder for ApngEncoder {
+  fn encode(&self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let (frames, width, height) = self.geometry(data.len()).ok_or(ApngError::TooLarge {
+      len: data.len(),
+      max: self.frame_capacity() * self.max_frames as usize,
+    })?;
+    let frame_size = width as usize * height as usize * BYTES_PER_PIXEL;
+
+    let mut cursor = Cursor::new(Vec::new());
+    {
+      let mut enc = png::Encoder::new(&mut cursor, width, height);
+      enc.set_depth(BitDepth::Eight);
+      enc.set_color(ColorType::Rgba);
+      enc.set_compression(Compression::Fast);
+      enc.set_animated(frames, 0)?;
+      let mut writer = enc.write_header()?;
+      let mut frame = vec![0; frame_size];
+      for chunk_index in 0..frames as usize {
+        let chunk = data
+          .get(chunk_index * frame_size..)
+          .map(|i| &i[..i.len().min(frame_size)])
+          .unwrap_or_default();
+        frame[..chunk.len()].copy_from_slice(chunk);
+        frame[chunk.len()..].fill(0);
+        writer.write_image_data(&frame)?;
+      }
+      writer.finish()?;
+    }
+    Ok(cursor.into_inner())
+  }
+
+  fn decode(&self, data: &[u8], data_len: usize) -> anyhow::Result<Vec<u8>> {
+    let decoder = png::Decoder::new(data);
+    let mut reader = decoder.read_info()?;
+    let frames = reader
+      .info()
+      .animation_control()
+      .map(|actl| actl.num_frames)
+      .unwrap_or(1);
+    let mut out = Vec::with_capacity(data_len);
+    let mut buf = vec![0; reader.output_buffer_size()];
+    for _ in 0..frames {
+      if out.len() >= data_len {
+        break;
+      }
+      let info = reader.next_frame(&mut buf)?;
+      out.extend_from_slice(&buf[..info.buffer_size()]);
+    }
+    if out.len() < data_len {
+      Err(ApngError::OutOfBound {
+        size: data_len,
+        bound: out.len(),
+      })?;
+    }
+    out.truncate(data_len);
+    Ok(out)
+  }
+
+  fn max_payload(&self) -> Option<usize> {
+    Some(self.frame_capacity() * self.max_frames as usize)
+  }
+
+  fn max_payload_for(&self, file_bytes: u64) -> Option<usize> {
+    Some(raw_bytes_within(file_bytes).min(self.frame_capacity() * self.max_frames as usize))
+  }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ApngError {
+  #[error(
+    "Input is too large to process, {} KiB / {} KiB ",
+    *len as f64 / 1024.0,
+    *max as f64 / 1024.0)
+  ]
+  TooLarge { len: usize, max: usize },
+  #[error("Size {size} out of bound {bound}")]
+  OutOfBound { size: usize, bound: usize },
+  #[error("An encoding error occurred during apng enc/dec {0}")]
+  Encoding(
+    #[from]
+    #[source]
+    EncodingError,
+  ),
+  #[error("An encoding error occurred during apng enc/dec {0}")]
+  Decoding(
+    #[from]
+    #[source]
+    DecodingError,
+  ),
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::encoder::apng::ApngEncoder;
+  use crate::encoder::Encoder;
+
+  fn sample(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 131 % 251) as u8).collect()
+  }
+
+  #[test]
+  fn geometry_should_fit() {
+    let enc = ApngEncoder::default();
+    assert_eq!(enc.geometry(1), Some((1, 1, 1)));
+    assert_eq!(enc.geometry(4 * 1024 * 1024), Some((1, 1024, 1024)));
+    assert_eq!(enc.geometry(4 * 1024 * 1024 + 1), Some((2, 725, 724)));
+    assert_eq!(enc.geometry(256 * 1024 * 1024), Some((64, 1024, 1024)));
+    assert_eq!(enc.geometry(256 * 1024 * 1024 + 1), None);
+  }
+
+  #[test]
+  fn encode_decode_frames() {
+    let enc = ApngEncoder {
+      frame_side: 64,
+      max_frames: 8,
+    };
+    for len in [0, 1, 64 * 64 * 4, 64 * 64 * 4 + 1, 114514] {
+      let data = sample(len);
+      let encoded = enc.encode(&data).unwrap();
+      assert_eq!(enc.decode(&encoded, len).unwrap(), data);
+    }
+    assert!(enc.encode(&sample(64 * 64 * 4 * 8 + 1)).is_err());
+  }
+}
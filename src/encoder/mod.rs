@@ -1,16 +1,83 @@
-use async_trait::async_trait;
-
+pub mod apng;
 pub mod png;
+pub mod robust;
+pub mod stego;
+pub mod webp;
 
-#[async_trait]
-pub trait Encoder {
-  /// Encode data to png binary with padding
-  async fn encode(&self, data: &[u8]) -> anyhow::Result<Vec<u8>>;
+/// Encoders are CPU-bound and synchronous, run them on a blocking thread in async context
+pub trait Encoder: Send + Sync {
+  /// Encode data to image binary with padding
+  fn encode(&self, data: &[u8]) -> anyhow::Result<Vec<u8>>;
 
-  /// Decode real data from png binary `data` with certain length
+  /// Decode real data from image binary `data` with certain length
   ///
   /// # Arguments
   ///
   /// * `data_len` - Real data length, without padding
-  async fn decode(&self, data: &[u8], length: usize) -> anyhow::Result<Vec<u8>>;
+  fn decode(&self, data: &[u8], length: usize) -> anyhow::Result<Vec<u8>>;
+
+  /// Max data length one image can hold
+  ///
+  /// # Return
+  ///
+  /// Return [None] if there is no limit known in advance
+  fn max_payload(&self) -> Option<usize> {
+    None
+  }
+
+  /// Max data length whose image is at most `file_bytes`, even if the data doesn't compress
+  ///
+  /// # Return
+  ///
+  /// Return [None] if the image size can't be bounded in advance
+  fn max_payload_for(&self, _file_bytes: u64) -> Option<usize> {
+    None
+  }
+}
+
+/// Headers, chunks and deflate blocks of an image take less than this and
+/// [PROPORTIONAL_OVERHEAD] of the pixels
+const FIXED_OVERHEAD: u64 = 8 * 1024;
+/// Row filters and padding take less than `1 / PROPORTIONAL_OVERHEAD` of the pixels
+const PROPORTIONAL_OVERHEAD: u64 = 128;
+
+/// Bytes of raw pixels a PNG or lossless WebP of at most `file_bytes` can hold,
+/// assuming they are stored without compression
+fn raw_bytes_within(file_bytes: u64) -> usize {
+  let raw =
+    file_bytes.saturating_sub(FIXED_OVERHEAD) * PROPORTIONAL_OVERHEAD / (PROPORTIONAL_OVERHEAD + 1);
+  usize::try_from(raw).unwrap_or(usize::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::encoder::apng::ApngEncoder;
+  use crate::encoder::png::PngEncoder;
+  use crate::encoder::stego::StegoEncoder;
+  use crate::encoder::webp::WebpEncoder;
+  use crate::encoder::Encoder;
+
+  #[test]
+  fn incompressible_payload_fits_file_bytes() {
+    let encoders: [(&str, Box<dyn Encoder>); 4] = [
+      ("png", Box::new(PngEncoder::default())),
+      ("webp", Box::new(WebpEncoder())),
+      ("apng", Box::new(ApngEncoder::default())),
+      ("stego", Box::new(StegoEncoder::default())),
+    ];
+    for (name, encoder) in encoders {
+      for file_bytes in [10 * 1024, 100 * 1024, 1024 * 1024] {
+        let len = encoder.max_payload_for(file_bytes).unwrap();
+        assert!(len > 0, "{name}");
+        let mut data = vec![0; len];
+        blake3::Hasher::new().finalize_xof().fill(&mut data);
+        let image = encoder.encode(&data).unwrap();
+        assert!(
+          image.len() as u64 <= file_bytes,
+          "{name}: {len} bytes encoded as {} > {file_bytes}",
+          image.len()
+        );
+      }
+    }
+  }
 }
@@ -2,36 +2,41 @@ use std::cmp::Ordering;
 use std::io;
 use std::io::{Cursor, Write};
 
-use async_trait::async_trait;
 use png::{BitDepth, ColorType, Compression, DecodingError, EncodingError};
 
-use super::Encoder;
+use super::{raw_bytes_within, Encoder};
 
-pub struct PngEncoder();
+/// Encoder storing data as raw pixels of a PNG image
+///
+/// Dimensions, bit depth and color type are computed from the data length,
+/// to keep the padding as small as the [ImageLimits] allow.
+#[derive(Default)]
+pub struct PngEncoder {
+  limits: ImageLimits,
+}
+
+impl PngEncoder {
+  pub fn with_limits(limits: ImageLimits) -> PngEncoder {
+    PngEncoder { limits }
+  }
+
+  /// Decode all bytes of the image, with the padding
+  pub fn decode_all(&self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let decoder = png::Decoder::new(data);
+    let mut reader = decoder.read_info().map_err(PngError::Decoding)?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).map_err(PngError::Decoding)?;
+    buf.truncate(info.buffer_size());
+    Ok(buf)
+  }
+}
 
-#[async_trait]
 impl Encoder for PngEncoder {
-  async fn encode(&self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
-    let modifier = MIB_MATRIX
-      .iter()
-      .find(|(size, _)| data.len() == *size)
-      .or_else(|| MIB_MATRIX.iter().find(|(size, _)| data.len() <= *size))
-      .map(|(_, func)| func);
-
-    let mut metadata = Metadata::default();
-
-    if let Some(func) = modifier {
-      func(&mut metadata);
-    } else {
-      Err(PngError::TooLarge {
-        len: data.len(),
-        max: *MIB_MATRIX
-          .iter()
-          .rfind(|_| true)
-          .map(|(size, _)| size)
-          .unwrap(),
-      })?;
-    }
+  fn encode(&self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut metadata = Metadata::fit(data.len(), &self.limits).ok_or(PngError::TooLarge {
+      len: data.len(),
+      max: self.limits.max_payload(),
+    })?;
 
     let padding_err = || {
       Err(PngError::Padding {
@@ -55,31 +60,84 @@ impl Encoder for PngEncoder {
       let mut enc = png::Encoder::new(&mut cursor, metadata.width, metadata.height);
       enc.set_depth(metadata.bit_depth);
       enc.set_color(metadata.color_type);
+      enc.set_compression(metadata.compression);
       let mut writer = enc.write_header().map_err(PngError::Encoding)?;
       let mut stream = writer.stream_writer().map_err(PngError::Encoding)?;
       stream.write_all(data).map_err(PngError::Io)?;
       let to_pad_size = metadata.byte_to_padding(data.len()).unwrap();
       stream
-        .write_all(&*vec![0; to_pad_size])
+        .write_all(&vec![0; to_pad_size])
         .map_err(PngError::Io)?;
       stream.finish().map_err(PngError::Encoding)?;
     }
     Ok(cursor.into_inner())
   }
 
-  async fn decode(&self, data: &[u8], data_len: usize) -> anyhow::Result<Vec<u8>> {
-    let decoder = png::Decoder::new(data);
-    let mut reader = decoder.read_info().map_err(PngError::Decoding)?;
-    let mut buf = vec![0; reader.output_buffer_size()];
-    let info = reader.next_frame(&mut buf).map_err(PngError::Decoding)?;
-    if info.buffer_size() < data_len {
+  fn decode(&self, data: &[u8], data_len: usize) -> anyhow::Result<Vec<u8>> {
+    let mut buf = self.decode_all(data)?;
+    if buf.len() < data_len {
       Err(PngError::OutOfBound {
         size: data_len,
-        bound: info.buffer_size(),
+        bound: buf.len(),
       })?;
     }
-    let pad_len = info.buffer_size() - data_len;
-    Ok(buf[..info.buffer_size() - pad_len].to_vec())
+    buf.truncate(data_len);
+    Ok(buf)
+  }
+
+  fn max_payload(&self) -> Option<usize> {
+    Some(self.limits.max_payload())
+  }
+
+  fn max_payload_for(&self, file_bytes: u64) -> Option<usize> {
+    Some(raw_bytes_within(file_bytes).min(self.limits.max_payload()))
+  }
+}
+
+/// Constraints of images accepted by a host
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ImageLimits {
+  /// Max width and height, in pixels
+  pub max_side: u32,
+  /// Max `width * height`
+  pub max_pixels: u64,
+}
+
+impl Default for ImageLimits {
+  fn default() -> Self {
+    ImageLimits {
+      max_side: 16384,
+      // 12 MiB with 16-bit RGBA
+      max_pixels: 1024 * 1536,
+    }
+  }
+}
+
+impl ImageLimits {
+  /// Max data length a PNG within limits can hold
+  pub fn max_payload(&self) -> usize {
+    let side = self.max_side as u64;
+    let max_bpp = PIXEL_FORMATS.iter().map(|(_, _, bpp)| *bpp).max().unwrap();
+    let pixels = (1..=side)
+      .map(|width| width * (self.max_pixels / width).min(side))
+      .max()
+      .unwrap_or(0);
+    pixels as usize * max_bpp
+  }
+
+  /// Find `(width, height)` of at least `pixels` pixels, as square as possible
+  fn dimensions(&self, pixels: u64) -> Option<(u64, u64)> {
+    let side = self.max_side as u64;
+    if pixels > self.max_pixels || pixels > side * side {
+      return None;
+    }
+    let fits = |width: u64| {
+      let height = pixels.div_ceil(width);
+      (height <= side && width * height <= self.max_pixels).then_some((width, height))
+    };
+    let width = ((pixels as f64).sqrt().ceil() as u64).clamp(1, side);
+    // Close to `max_pixels`, a wider image may waste fewer pixels in the last row
+    fits(width).or_else(|| (width + 1..=side).find_map(fits))
   }
 }
 
@@ -128,21 +186,36 @@ struct Metadata {
 }
 
 impl Metadata {
-  fn default() -> Metadata {
-    Metadata {
-      width: 1024,
-      height: 1024,
-      bit_depth: BitDepth::Sixteen,
-      color_type: ColorType::Rgba,
-      compression: Compression::Fast,
-    }
+  /// Find the metadata which holds `data_len` bytes with the least padding
+  ///
+  /// # Return
+  ///
+  /// Return [None] if `data_len` can't fit in `limits`
+  fn fit(data_len: usize, limits: &ImageLimits) -> Option<Metadata> {
+    let data_len = data_len.max(1);
+    PIXEL_FORMATS
+      .iter()
+      .filter_map(|&(bit_depth, color_type, bpp)| {
+        let (width, height) = limits.dimensions(data_len.div_ceil(bpp) as u64)?;
+        let padding = (width * height) as usize * bpp - data_len;
+        Some((padding, bpp, width, height, bit_depth, color_type))
+      })
+      // Least padding, then fewest pixels
+      .min_by_key(|&(padding, bpp, ..)| (padding, usize::MAX - bpp))
+      .map(|(_, _, width, height, bit_depth, color_type)| Metadata {
+        width: width as u32,
+        height: height as u32,
+        bit_depth,
+        color_type,
+        compression: Compression::Fast,
+      })
   }
 
   fn size(&self) -> Option<usize> {
     let pixel_size_bit =
       bit_depth_size(&self.bit_depth) as usize * color_type_multiple(&self.color_type) as usize;
     let size = self.width as usize * self.height as usize * pixel_size_bit;
-    if size % 8 != 0 {
+    if !size.is_multiple_of(8) {
       return None;
     }
     Some(size / 8)
@@ -182,100 +255,16 @@ fn color_type_multiple(color_type: &ColorType) -> u32 {
   }
 }
 
-type MetadataBuilder = fn(&mut Metadata);
-
-// (byte, func)
-const MIB_MATRIX: [(usize, MetadataBuilder); 15] = [
-  (16384 /* 16 KiB */, |i: &mut Metadata| {
-    i.width = 64;
-    i.height = 64;
-    i.bit_depth = BitDepth::Eight; // 1 byte
-    i.color_type = ColorType::Rgba; // 4
-  }),
-  (32768 /* 32 KiB */, |i: &mut Metadata| {
-    i.width = 64;
-    i.height = 128;
-    i.bit_depth = BitDepth::Eight; // 1 byte
-    i.color_type = ColorType::Rgba; // 4
-  }),
-  (65536 /* 64 KiB */, |i: &mut Metadata| {
-    i.width = 128;
-    i.height = 128;
-    i.bit_depth = BitDepth::Eight; // 1 byte
-    i.color_type = ColorType::Rgba; // 4
-  }),
-  (131072 /* 128 KiB */, |i: &mut Metadata| {
-    i.width = 128;
-    i.height = 256;
-    i.bit_depth = BitDepth::Eight; // 1 byte
-    i.color_type = ColorType::Rgba; // 4
-  }),
-  (262144 /* 256 KiB */, |i: &mut Metadata| {
-    i.width = 256;
-    i.height = 256;
-    i.bit_depth = BitDepth::Eight; // 1 byte
-    i.color_type = ColorType::Rgba; // 4
-  }),
-  (524_288 /* 512 KiB */, |i: &mut Metadata| {
-    i.width = 256;
-    i.height = 512;
-    i.bit_depth = BitDepth::Eight; // 1 byte
-    i.color_type = ColorType::Rgba; // 4
-  }),
-  (1024 * 1024, |i: &mut Metadata| {
-    i.width = 512;
-    i.height = 512;
-    i.bit_depth = BitDepth::Eight; // 1 byte
-    i.color_type = ColorType::Rgba; // 4
-  }),
-  (1_572_864 /* 1024 * 1024 * 1.5 */, |i: &mut Metadata| {
-    i.width = 512;
-    i.height = 1024;
-    i.bit_depth = BitDepth::Eight; // 1 byte
-    i.color_type = ColorType::Rgb; // 3
-  }),
-  (1024 * 1024 * 2, |i: &mut Metadata| {
-    i.width = 1024;
-    i.height = 1024;
-    i.bit_depth = BitDepth::Eight; // 1 byte
-    i.color_type = ColorType::GrayscaleAlpha; // 2
-  }),
-  (2_621_440 /* 1024*1024*2.5 */, |i: &mut Metadata| {
-    i.width = 512;
-    i.height = 1280;
-    i.bit_depth = BitDepth::Eight; // 1 byte
-    i.color_type = ColorType::Rgba; // 4
-  }),
-  (1024 * 1024 * 3, |i: &mut Metadata| {
-    i.width = 1024;
-    i.height = 1024;
-    i.bit_depth = BitDepth::Eight; // 1 byte
-    i.color_type = ColorType::Rgb; // 3
-  }),
-  (1024 * 1024 * 4, |i: &mut Metadata| {
-    i.width = 1024;
-    i.height = 1024;
-    i.bit_depth = BitDepth::Eight; // 1 byte
-    i.color_type = ColorType::Rgba; // 4
-  }),
-  (1024 * 1024 * 6, |i: &mut Metadata| {
-    i.width = 1024;
-    i.height = 1024;
-    i.bit_depth = BitDepth::Sixteen; // 2 byte
-    i.color_type = ColorType::Rgb; // 3
-  }),
-  (1024 * 1024 * 8, |i: &mut Metadata| {
-    i.width = 1024;
-    i.height = 1024;
-    i.bit_depth = BitDepth::Sixteen; // 2 byte
-    i.color_type = ColorType::Rgba; // 4
-  }),
-  (1024 * 1024 * 12, |i: &mut Metadata| {
-    i.width = 1024;
-    i.height = 1536;
-    i.bit_depth = BitDepth::Sixteen; // 2 byte
-    i.color_type = ColorType::Rgba; // 4
-  }),
+/// (bit_depth, color_type, bytes per pixel)
+///
+/// Sub-byte depths and duplicated pixel sizes are left out
+const PIXEL_FORMATS: [(BitDepth, ColorType, usize); 6] = [
+  (BitDepth::Eight, ColorType::Grayscale, 1),
+  (BitDepth::Eight, ColorType::GrayscaleAlpha, 2),
+  (BitDepth::Eight, ColorType::Rgb, 3),
+  (BitDepth::Eight, ColorType::Rgba, 4),
+  (BitDepth::Sixteen, ColorType::Rgb, 6),
+  (BitDepth::Sixteen, ColorType::Rgba, 8),
 ];
 
 #[cfg(test)]
@@ -283,14 +272,15 @@ mod tests {
   use std::sync::Mutex;
 
   use png::{BitDepth, ColorType, Compression};
+  use proptest::prelude::*;
 
-  use crate::encoder::png::{Metadata, PngEncoder};
+  use crate::encoder::png::{ImageLimits, Metadata, PngEncoder, PIXEL_FORMATS};
   use crate::encoder::Encoder;
 
   #[tokio::test]
   async fn encode_png() {
-    let enc = PngEncoder();
-    enc.encode(&[1, 2, 3, 4]).await.unwrap();
+    let enc = PngEncoder::default();
+    enc.encode(&[1, 2, 3, 4]).unwrap();
   }
 
   #[tokio::test]
@@ -334,8 +324,8 @@ mod tests {
 
     ARR.iter().for_each(|i| {
       let handle = tokio::spawn(async {
-        let enc = PngEncoder();
-        enc.encode(&vec![0; *i]).await.unwrap();
+        let enc = PngEncoder::default();
+        enc.encode(&vec![0; *i]).unwrap();
       });
       let mut list = list.lock().unwrap();
       list.push(handle);
@@ -343,8 +333,8 @@ mod tests {
 
     ARR.iter().for_each(|i| {
       let handle = tokio::spawn(async {
-        let enc = PngEncoder();
-        enc.encode(&vec![0; *i - 1000]).await.unwrap();
+        let enc = PngEncoder::default();
+        enc.encode(&vec![0; *i - 1000]).unwrap();
       });
       let mut list = list.lock().unwrap();
       list.push(handle);
@@ -356,4 +346,63 @@ mod tests {
       cur_thread.await.unwrap();
     }
   }
+
+  /// `len` fits in the limits, padded by less than one row
+  fn assert_fits(len: usize, limits: &ImageLimits) {
+    let metadata = Metadata::fit(len, limits).unwrap();
+    assert!(metadata.width <= limits.max_side && metadata.height <= limits.max_side);
+    assert!(metadata.width as u64 * metadata.height as u64 <= limits.max_pixels);
+    let bpp = PIXEL_FORMATS
+      .iter()
+      .find(|(depth, color, _)| *depth == metadata.bit_depth && *color == metadata.color_type)
+      .map(|(_, _, bpp)| *bpp)
+      .unwrap();
+    let padding = metadata.byte_to_padding(len).unwrap();
+    assert!(padding < (metadata.width as usize + 1) * bpp, "len {len}");
+  }
+
+  #[test]
+  fn fit_boundary_sizes() {
+    let limits = ImageLimits::default();
+    let max = limits.max_payload();
+    assert_eq!(max, 12 * 1024 * 1024);
+    let powers = (0..24).flat_map(|i| [(1 << i) - 1, 1 << i, (1 << i) + 1]);
+    let pixels = limits.max_pixels as usize;
+    let near_max = [pixels, 2 * pixels, 4 * pixels, 6 * pixels, max - 1, max];
+    for len in powers.chain(near_max).filter(|len| (1..=max).contains(len)) {
+      assert_fits(len, &limits);
+    }
+    assert!(Metadata::fit(max + 1, &limits).is_none());
+  }
+
+  #[test]
+  fn fit_small_limits() {
+    let limits = ImageLimits {
+      max_side: 100,
+      max_pixels: 5000,
+    };
+    assert_eq!(limits.max_payload(), 5000 * 8);
+    let metadata = Metadata::fit(limits.max_payload(), &limits).unwrap();
+    assert_eq!((metadata.width, metadata.height), (100, 50));
+    assert!(Metadata::fit(limits.max_payload() + 1, &limits).is_none());
+  }
+
+  proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn encode_decode_any_size(data in proptest::collection::vec(any::<u8>(), 1..200_000)) {
+      let enc = PngEncoder::default();
+      let encoded = enc.encode(&data).unwrap();
+      let decoded = enc.decode(&encoded, data.len()).unwrap();
+      prop_assert_eq!(decoded, data);
+    }
+  }
+
+  proptest! {
+    #[test]
+    fn fit_any_size(len in 1..=12 * 1024 * 1024usize) {
+      assert_fits(len, &ImageLimits::default());
+    }
+  }
 }
@@ -0,0 +1,360 @@
+use std::io::{Cursor, Write};
+
+use png::{BitDepth, ColorType, Compression, DecodingError, EncodingError, Transformations};
+
+use self::rs::{ReedSolomon, RsError, CODEWORD_LEN};
+
+use super::Encoder;
+
+mod rs;
+
+/// Max side length of the image, in pixels
+const MAX_SIDE: usize = 16384;
+
+/// Pixel value of bit `0`
+const DARK: u8 = 0;
+/// Pixel value of bit `1`
+const LIGHT: u8 = 255;
+
+/// Encoder that survives lossy recompression, e.g. hosts converting uploads to JPEG
+///
+/// Data is split into Reed-Solomon codewords, which are interleaved byte by byte,
+/// so a damaged area of the image spreads its errors across many codewords.
+/// Every bit is then drawn as a `cell * cell` grayscale square.
+/// With the default 4 pixel cells, 4 cells share one 8x8 JPEG block.
+pub struct RobustEncoder {
+  /// Side length of one bit, in pixels
+  cell: u32,
+  /// Parity bytes per 255 bytes codeword, corrects up to `parity / 2` bytes
+  parity: usize,
+}
+
+impl Default for RobustEncoder {
+  fn default() -> Self {
+    RobustEncoder {
+      cell: 4,
+      parity: 32,
+    }
+  }
+}
+
+impl RobustEncoder {
+  /// Max payload an image of `max_side * max_side` can hold
+  pub fn capacity(&self) -> usize {
+    let cells_per_side = MAX_SIDE / self.cell as usize;
+    let codewords = cells_per_side * cells_per_side / 8 / CODEWORD_LEN;
+    codewords * self.message_len()
+  }
+
+  fn message_len(&self) -> usize {
+    CODEWORD_LEN - self.parity
+  }
+
+  fn codewords(&self, data_len: usize) -> usize {
+    data_len.max(1).div_ceil(self.message_len())
+  }
+
+  /// `(columns, rows)` of cells to hold `codewords` codewords
+  fn geometry(&self, codewords: usize) -> Option<(usize, usize)> {
+    let bits = codewords * CODEWORD_LEN * 8;
+    let cols = (bits as f64).sqrt().ceil() as usize;
+    let rows = bits.div_ceil(cols);
+    let max_cells = MAX_SIDE / self.cell as usize;
+    if cols > max_cells || rows > max_cells {
+      return None;
+    }
+    Some((cols, rows))
+  }
+}
+
+impl Encoder for RobustEncoder {
+  fn encode(&self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let codewords = self.codewords(data.len());
+    let (cols, rows) = self.geometry(codewords).ok_or(RobustError::TooLarge {
+      len: data.len(),
+      max: self.capacity(),
+    })?;
+
+    let rs = ReedSolomon::new(self.parity);
+    let mut stream = vec![0; codewords * CODEWORD_LEN];
+    let mut msg = vec![0; self.message_len()];
+    for c in 0..codewords {
+      let chunk = data
+        .get(c * self.message_len()..)
+        .map(|i| &i[..i.len().min(self.message_len())])
+        .unwrap_or_default();
+      msg.fill(0);
+      msg[..chunk.len()].copy_from_slice(chunk);
+      for (j, byte) in rs.encode(&msg).into_iter().enumerate() {
+        stream[j * codewords + c] = byte;
+      }
+    }
+
+    let cell = self.cell as usize;
+    let width = cols * cell;
+    let height = rows * cell;
+    let mut pixels = vec![DARK; width * height];
+    for (bit_index, bit) in bits(&stream).enumerate() {
+      if !bit {
+        continue;
+      }
+      let x = bit_index % cols * cell;
+      let y = bit_index / cols * cell;
+      for row in y..y + cell {
+        pixels[row * width + x..row * width + x + cell].fill(LIGHT);
+      }
+    }
+
+    let mut cursor = Cursor::new(Vec::new());
+    {
+      let mut enc = png::Encoder::new(&mut cursor, width as u32, height as u32);
+      enc.set_depth(BitDepth::Eight);
+      enc.set_color(ColorType::Grayscale);
+      enc.set_compression(Compression::Default);
+      let mut writer = enc.write_header().map_err(RobustError::Encoding)?;
+      let mut stream = writer.stream_writer().map_err(RobustError::Encoding)?;
+      stream.write_all(&pixels)?;
+      stream.finish().map_err(RobustError::Encoding)?;
+    }
+    Ok(cursor.into_inner())
+  }
+
+  fn decode(&self, data: &[u8], data_len: usize) -> anyhow::Result<Vec<u8>> {
+    let codewords = self.codewords(data_len);
+    let (cols, _) = self.geometry(codewords).ok_or(RobustError::TooLarge {
+      len: data_len,
+      max: self.capacity(),
+    })?;
+    let image = Luma::load(data)?;
+    let cell = self.cell as usize;
+    if image.width < cols * cell {
+      Err(RobustError::OutOfBound {
+        size: cols * cell,
+        bound: image.width,
+      })?;
+    }
+
+    let mut stream = vec![0; codewords * CODEWORD_LEN];
+    for (byte_index, byte) in stream.iter_mut().enumerate() {
+      for bit in 0..8 {
+        let bit_index = byte_index * 8 + bit;
+        let x = bit_index % cols * cell;
+        let y = bit_index / cols * cell;
+        let mean = image.cell_mean(x, y, cell).ok_or(RobustError::OutOfBound {
+          size: (y + cell) * image.width,
+          bound: image.pixels.len(),
+        })?;
+        if mean >= (DARK as u32 + LIGHT as u32) / 2 {
+          *byte |= 0x80 >> bit;
+        }
+      }
+    }
+
+    let rs = ReedSolomon::new(self.parity);
+    let mut out = Vec::with_capacity(codewords * self.message_len());
+    let mut codeword = vec![0; CODEWORD_LEN];
+    for c in 0..codewords {
+      for (j, byte) in codeword.iter_mut().enumerate() {
+        *byte = stream[j * codewords + c];
+      }
+      rs.correct(&mut codeword)
+        .map_err(|err| RobustError::Corrupted { codeword: c, err })?;
+      out.extend_from_slice(&codeword[..self.message_len()]);
+    }
+    out.truncate(data_len);
+    Ok(out)
+  }
+
+  fn max_payload(&self) -> Option<usize> {
+    Some(self.capacity())
+  }
+
+  // Cells compress far below their raw pixels, but how far depends on the data,
+  // so only the size of encoded blocks is checked
+}
+
+/// Iterate bits of `bytes`, from the most significant bit
+fn bits(bytes: &[u8]) -> impl Iterator<Item = bool> + '_ {
+  bytes
+    .iter()
+    .flat_map(|byte| (0..8).map(move |bit| byte & (0x80 >> bit) != 0))
+}
+
+/// 8-bit grayscale image
+struct Luma {
+  width: usize,
+  pixels: Vec<u8>,
+}
+
+impl Luma {
+  /// Load a PNG or JPEG image as grayscale
+  fn load(data: &[u8]) -> Result<Luma, RobustError> {
+    if data.starts_with(&[0x89, b'P', b'N', b'G']) {
+      Luma::load_png(data)
+    } else if data.starts_with(&[0xff, 0xd8]) {
+      Luma::load_jpeg(data)
+    } else {
+      Err(RobustError::UnknownFormat)
+    }
+  }
+
+  fn load_png(data: &[u8]) -> Result<Luma, RobustError> {
+    let mut decoder = png::Decoder::new(data);
+    decoder.set_transformations(Transformations::EXPAND | Transformations::STRIP_16);
+    let mut reader = decoder.read_info()?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf)?;
+    buf.truncate(info.buffer_size());
+    let channels = match info.color_type {
+      ColorType::Grayscale | ColorType::Indexed => 1,
+      ColorType::GrayscaleAlpha => 2,
+      ColorType::Rgb => 3,
+      ColorType::Rgba => 4,
+    };
+    Ok(Luma {
+      width: info.width as usize,
+      pixels: to_luma(&buf, channels),
+    })
+  }
+
+  fn load_jpeg(data: &[u8]) -> Result<Luma, RobustError> {
+    let mut decoder = jpeg_decoder::Decoder::new(data);
+    let buf = decoder.decode()?;
+    let info = decoder.info().ok_or(RobustError::UnknownFormat)?;
+    let channels = match info.pixel_format {
+      jpeg_decoder::PixelFormat::L8 => 1,
+      jpeg_decoder::PixelFormat::RGB24 => 3,
+      _ => Err(RobustError::UnknownFormat)?,
+    };
+    Ok(Luma {
+      width: info.width as usize,
+      pixels: to_luma(&buf, channels),
+    })
+  }
+
+  /// Mean of a cell, the outermost pixels are skipped as they blur into neighbours
+  fn cell_mean(&self, x: usize, y: usize, cell: usize) -> Option<u32> {
+    let margin = if cell >= 4 { 1 } else { 0 };
+    let (mut sum, mut count) = (0, 0);
+    for row in y + margin..y + cell - margin {
+      let start = row * self.width + x + margin;
+      let pixels = self.pixels.get(start..start + cell - margin * 2)?;
+      sum += pixels.iter().map(|&i| i as u32).sum::<u32>();
+      count += pixels.len() as u32;
+    }
+    Some(sum / count)
+  }
+}
+
+fn to_luma(buf: &[u8], channels: usize) -> Vec<u8> {
+  match channels {
+    1 => buf.to_vec(),
+    2 => buf.chunks_exact(2).map(|i| i[0]).collect(),
+    _ => buf
+      .chunks_exact(channels)
+      .map(|i| ((i[0] as u32 * 299 + i[1] as u32 * 587 + i[2] as u32 * 114) / 1000) as u8)
+      .collect(),
+  }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum RobustError {
+  #[error(
+    "Input is too large to process, {} KiB / {} KiB ",
+    *len as f64 / 1024.0,
+    *max as f64 / 1024.0)
+  ]
+  TooLarge { len: usize, max: usize },
+  #[error("Size {size} out of bound {bound}")]
+  OutOfBound { size: usize, bound: usize },
+  #[error("Unknown image format, only PNG and JPEG are supported")]
+  UnknownFormat,
+  #[error("Codeword {codeword} is corrupted beyond repair: {err}")]
+  Corrupted { codeword: usize, err: RsError },
+  #[error("An encoding error occurred during png enc/dec {0}")]
+  Encoding(
+    #[from]
+    #[source]
+    EncodingError,
+  ),
+  #[error("An encoding error occurred during png enc/dec {0}")]
+  Decoding(
+    #[from]
+    #[source]
+    DecodingError,
+  ),
+  #[error("An error occurred during jpeg decoding {0}")]
+  Jpeg(
+    #[from]
+    #[source]
+    jpeg_decoder::Error,
+  ),
+  #[error("An I/O error occurred during png enc/dec {0}")]
+  Io(
+    #[from]
+    #[source]
+    std::io::Error,
+  ),
+}
+
+#[cfg(test)]
+mod tests {
+  use jpeg_encoder::{ColorType, SamplingFactor};
+
+  use crate::encoder::robust::{Luma, RobustEncoder};
+  use crate::encoder::Encoder;
+
+  /// Qualities to test, override with `CUTIS_JPEG_QUALITY=50,75`
+  fn qualities() -> Vec<u8> {
+    std::env::var("CUTIS_JPEG_QUALITY")
+      .map(|i| i.split(',').map(|q| q.trim().parse().unwrap()).collect())
+      .unwrap_or_else(|_| vec![50, 75, 90])
+  }
+
+  /// Recompress `image` to JPEG like most image hosts do,
+  /// as RGB with 4:2:0 chroma subsampling
+  fn jpeg_round_trip(image: &[u8], quality: u8) -> Vec<u8> {
+    let luma = Luma::load(image).unwrap();
+    let height = luma.pixels.len() / luma.width;
+    let rgb: Vec<u8> = luma.pixels.iter().flat_map(|&i| [i, i, i]).collect();
+    let mut jpeg = Vec::new();
+    let mut enc = jpeg_encoder::Encoder::new(&mut jpeg, quality);
+    enc.set_sampling_factor(SamplingFactor::F_2_2);
+    enc
+      .encode(&rgb, luma.width as u16, height as u16, ColorType::Rgb)
+      .unwrap();
+    jpeg
+  }
+
+  fn sample(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 131 % 251) as u8).collect()
+  }
+
+  #[test]
+  fn encode_decode_lossless() {
+    let enc = RobustEncoder::default();
+    for len in [0, 1, 223, 224, 20000] {
+      let data = sample(len);
+      let encoded = enc.encode(&data).unwrap();
+      assert_eq!(enc.decode(&encoded, len).unwrap(), data);
+    }
+  }
+
+  #[test]
+  fn survive_jpeg_recompression() {
+    let enc = RobustEncoder::default();
+    let data = sample(16 * 1024);
+    let encoded = enc.encode(&data).unwrap();
+    for quality in qualities() {
+      let jpeg = jpeg_round_trip(&encoded, quality);
+      let decoded = enc.decode(&jpeg, data.len());
+      assert_eq!(decoded.unwrap(), data, "quality {quality}");
+    }
+  }
+
+  #[test]
+  fn too_large() {
+    let enc = RobustEncoder::default();
+    assert!(enc.encode(&vec![0; enc.capacity() + 1]).is_err());
+  }
+}
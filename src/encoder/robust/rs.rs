@@ -0,0 +1,280 @@
+//! Reed-Solomon code over GF(2^8), with primitive polynomial `0x11d`
+//!
+//! Polynomials are stored from the highest degree to the lowest,
+//! a codeword is `[message][parity]` and can correct up to `parity / 2` byte errors.
+
+/// Length of a full codeword
+pub(super) const CODEWORD_LEN: usize = 255;
+
+const PRIMITIVE: u16 = 0x11d;
+
+lazy_static! {
+  static ref GF: GaloisField = GaloisField::new();
+}
+
+struct GaloisField {
+  exp: [u8; 512],
+  log: [u8; 256],
+}
+
+impl GaloisField {
+  fn new() -> GaloisField {
+    let mut exp = [0; 512];
+    let mut log = [0; 256];
+    let mut x: u16 = 1;
+    for (i, e) in exp.iter_mut().take(255).enumerate() {
+      *e = x as u8;
+      log[x as usize] = i as u8;
+      x <<= 1;
+      if x & 0x100 != 0 {
+        x ^= PRIMITIVE;
+      }
+    }
+    for i in 255..512 {
+      exp[i] = exp[i - 255];
+    }
+    GaloisField { exp, log }
+  }
+
+  fn mul(&self, x: u8, y: u8) -> u8 {
+    if x == 0 || y == 0 {
+      return 0;
+    }
+    self.exp[self.log[x as usize] as usize + self.log[y as usize] as usize]
+  }
+
+  fn div(&self, x: u8, y: u8) -> u8 {
+    debug_assert_ne!(y, 0, "divided by zero");
+    if x == 0 {
+      return 0;
+    }
+    self.exp[(self.log[x as usize] as usize + 255 - self.log[y as usize] as usize) % 255]
+  }
+
+  fn pow(&self, x: u8, power: i32) -> u8 {
+    self.exp[(self.log[x as usize] as i32 * power).rem_euclid(255) as usize]
+  }
+
+  fn inverse(&self, x: u8) -> u8 {
+    self.exp[255 - self.log[x as usize] as usize]
+  }
+
+  fn poly_scale(&self, poly: &[u8], x: u8) -> Vec<u8> {
+    poly.iter().map(|&i| self.mul(i, x)).collect()
+  }
+
+  fn poly_add(&self, p: &[u8], q: &[u8]) -> Vec<u8> {
+    let len = p.len().max(q.len());
+    let mut result = vec![0; len];
+    for (i, &coef) in p.iter().enumerate() {
+      result[i + len - p.len()] = coef;
+    }
+    for (i, &coef) in q.iter().enumerate() {
+      result[i + len - q.len()] ^= coef;
+    }
+    result
+  }
+
+  fn poly_mul(&self, p: &[u8], q: &[u8]) -> Vec<u8> {
+    let mut result = vec![0; p.len() + q.len() - 1];
+    for (j, &q_coef) in q.iter().enumerate() {
+      for (i, &p_coef) in p.iter().enumerate() {
+        result[i + j] ^= self.mul(p_coef, q_coef);
+      }
+    }
+    result
+  }
+
+  fn poly_eval(&self, poly: &[u8], x: u8) -> u8 {
+    poly[1..]
+      .iter()
+      .fold(poly[0], |acc, &coef| self.mul(acc, x) ^ coef)
+  }
+}
+
+pub(super) struct ReedSolomon {
+  parity: usize,
+  generator: Vec<u8>,
+}
+
+impl ReedSolomon {
+  pub(super) fn new(parity: usize) -> ReedSolomon {
+    let generator = (0..parity).fold(vec![1], |gen, i| {
+      GF.poly_mul(&gen, &[1, GF.pow(2, i as i32)])
+    });
+    ReedSolomon { parity, generator }
+  }
+
+  /// Append parity bytes to `msg`
+  ///
+  /// `msg.len() + parity` must not exceed [CODEWORD_LEN]
+  pub(super) fn encode(&self, msg: &[u8]) -> Vec<u8> {
+    debug_assert!(msg.len() + self.parity <= CODEWORD_LEN);
+    let mut out = vec![0; msg.len() + self.parity];
+    out[..msg.len()].copy_from_slice(msg);
+    for i in 0..msg.len() {
+      let coef = out[i];
+      if coef != 0 {
+        for (j, &gen_coef) in self.generator.iter().enumerate().skip(1) {
+          out[i + j] ^= GF.mul(gen_coef, coef);
+        }
+      }
+    }
+    out[..msg.len()].copy_from_slice(msg);
+    out
+  }
+
+  /// Correct errors in `codeword` in place, return the number of corrected bytes
+  pub(super) fn correct(&self, codeword: &mut [u8]) -> Result<usize, RsError> {
+    let parity = self.parity;
+    let synd = syndromes(codeword, parity);
+    if synd.iter().all(|&i| i == 0) {
+      return Ok(0);
+    }
+    let mut err_loc = error_locator(&synd, parity)?;
+    err_loc.reverse();
+    let err_pos = find_errors(&err_loc, codeword.len())?;
+    correct_errata(codeword, &synd, &err_pos)?;
+    if syndromes(codeword, parity).iter().any(|&i| i != 0) {
+      return Err(RsError::TooManyErrors);
+    }
+    Ok(err_pos.len())
+  }
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum RsError {
+  #[error("Too many errors to correct")]
+  TooManyErrors,
+  #[error("Failed to locate errors")]
+  Locate,
+}
+
+/// Syndromes with a leading zero, which makes the indices match the math
+fn syndromes(codeword: &[u8], parity: usize) -> Vec<u8> {
+  let mut synd = vec![0; parity + 1];
+  for (i, s) in synd.iter_mut().skip(1).enumerate() {
+    *s = GF.poly_eval(codeword, GF.pow(2, i as i32));
+  }
+  synd
+}
+
+/// Berlekamp-Massey algorithm
+fn error_locator(synd: &[u8], parity: usize) -> Result<Vec<u8>, RsError> {
+  let mut err_loc = vec![1];
+  let mut old_loc = vec![1];
+  let shift = synd.len() - parity;
+  for i in 0..parity {
+    let k = i + shift;
+    let mut delta = synd[k];
+    for j in 1..err_loc.len() {
+      delta ^= GF.mul(err_loc[err_loc.len() - 1 - j], synd[k - j]);
+    }
+    old_loc.push(0);
+    if delta != 0 {
+      if old_loc.len() > err_loc.len() {
+        let new_loc = GF.poly_scale(&old_loc, delta);
+        old_loc = GF.poly_scale(&err_loc, GF.inverse(delta));
+        err_loc = new_loc;
+      }
+      err_loc = GF.poly_add(&err_loc, &GF.poly_scale(&old_loc, delta));
+    }
+  }
+  let leading_zeros = err_loc.iter().take_while(|&&i| i == 0).count();
+  err_loc.drain(..leading_zeros);
+  if (err_loc.len() - 1) * 2 > parity {
+    return Err(RsError::TooManyErrors);
+  }
+  Ok(err_loc)
+}
+
+/// Chien search
+fn find_errors(err_loc: &[u8], len: usize) -> Result<Vec<usize>, RsError> {
+  let errs = err_loc.len() - 1;
+  let err_pos: Vec<_> = (0..len)
+    .filter(|&i| GF.poly_eval(err_loc, GF.pow(2, i as i32)) == 0)
+    .map(|i| len - 1 - i)
+    .collect();
+  if err_pos.len() != errs {
+    return Err(RsError::Locate);
+  }
+  Ok(err_pos)
+}
+
+/// Forney algorithm
+fn correct_errata(codeword: &mut [u8], synd: &[u8], err_pos: &[usize]) -> Result<(), RsError> {
+  let coef_pos: Vec<_> = err_pos.iter().map(|p| codeword.len() - 1 - p).collect();
+  let err_loc = coef_pos.iter().fold(vec![1], |loc, &i| {
+    GF.poly_mul(&loc, &GF.poly_add(&[1], &[GF.pow(2, i as i32), 0]))
+  });
+  let err_eval = {
+    let mut synd = synd.to_vec();
+    synd.reverse();
+    let product = GF.poly_mul(&synd, &err_loc);
+    // remainder of product / x^(err_loc.len())
+    product[product.len() - err_loc.len()..].to_vec()
+  };
+  let x: Vec<_> = coef_pos
+    .iter()
+    .map(|&i| GF.pow(2, -(255 - i as i32)))
+    .collect();
+  for (i, &xi) in x.iter().enumerate() {
+    let xi_inv = GF.inverse(xi);
+    let err_loc_prime = x
+      .iter()
+      .enumerate()
+      .filter(|&(j, _)| j != i)
+      .fold(1, |acc, (_, &xj)| GF.mul(acc, 1 ^ GF.mul(xi_inv, xj)));
+    if err_loc_prime == 0 {
+      return Err(RsError::Locate);
+    }
+    let y = GF.mul(xi, GF.poly_eval(&err_eval, xi_inv));
+    codeword[err_pos[i]] ^= GF.div(y, err_loc_prime);
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{ReedSolomon, RsError};
+
+  #[test]
+  fn correct_errors() {
+    let msg: Vec<u8> = (0..223).map(|i| (i * 31 % 256) as u8).collect();
+    let rs = ReedSolomon::new(32);
+    let encoded = rs.encode(&msg);
+    assert_eq!(encoded.len(), 255);
+    assert_eq!(&encoded[..223], &msg[..]);
+
+    let mut clean = encoded.clone();
+    assert_eq!(rs.correct(&mut clean), Ok(0));
+
+    let mut corrupted = encoded.clone();
+    for i in 0..16 {
+      corrupted[i * 15 + 3] ^= 0x5a + i as u8;
+    }
+    assert_eq!(rs.correct(&mut corrupted), Ok(16));
+    assert_eq!(corrupted, encoded);
+
+    let mut corrupted = encoded;
+    for i in 0..40 {
+      corrupted[i * 6] ^= 0xff;
+    }
+    assert!(matches!(
+      rs.correct(&mut corrupted),
+      Err(RsError::TooManyErrors | RsError::Locate)
+    ));
+  }
+
+  #[test]
+  fn correct_shortened_codeword() {
+    let msg = [1, 2, 3, 4, 5];
+    let rs = ReedSolomon::new(8);
+    let encoded = rs.encode(&msg);
+    let mut corrupted = encoded.clone();
+    corrupted[0] = 0;
+    corrupted[10] ^= 1;
+    assert_eq!(rs.correct(&mut corrupted), Ok(2));
+    assert_eq!(corrupted, encoded);
+  }
+}
@@ -0,0 +1,314 @@
+use std::io::{Cursor, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use png::{BitDepth, ColorType, Compression, DecodingError, EncodingError, Transformations};
+
+use super::{raw_bytes_within, Encoder};
+
+/// Side length limit of a generated cover
+const MAX_SIDE: usize = 16384;
+
+/// Encoder hiding data in the low bits of every RGB channel of a cover image
+///
+/// Without a cover, a smooth gradient just large enough for the data is generated.
+/// Channels not used by data keep the original bits of the cover.
+pub struct StegoEncoder {
+  cover: Option<Arc<Cover>>,
+  /// Low bits used per channel, one of `1`, `2`, `4`
+  bits: u8,
+}
+
+struct Cover {
+  width: u32,
+  height: u32,
+  /// RGB, 8 bits per channel
+  pixels: Vec<u8>,
+}
+
+impl Default for StegoEncoder {
+  fn default() -> Self {
+    StegoEncoder {
+      cover: None,
+      bits: 2,
+    }
+  }
+}
+
+impl StegoEncoder {
+  pub fn new(bits: u8) -> Result<StegoEncoder, StegoError> {
+    if ![1, 2, 4].contains(&bits) {
+      return Err(StegoError::Bits(bits));
+    }
+    Ok(StegoEncoder { cover: None, bits })
+  }
+
+  /// Use a PNG or JPEG image at `path` as the cover
+  pub fn with_cover(mut self, path: &Path) -> Result<StegoEncoder, StegoError> {
+    let data = std::fs::read(path)?;
+    self.cover = Some(Arc::new(Cover::load(&data)?));
+    Ok(self)
+  }
+
+  fn capacity_of(&self, width: u32, height: u32) -> usize {
+    width as usize * height as usize * 3 * self.bits as usize / 8
+  }
+
+  /// Generate a gradient cover which can hold `data_len` bytes
+  fn gradient(&self, data_len: usize) -> Option<Cover> {
+    let channels = (data_len.max(1) * 8).div_ceil(self.bits as usize);
+    let pixels = channels.div_ceil(3);
+    let width = (pixels as f64).sqrt().ceil() as usize;
+    let height = pixels.div_ceil(width);
+    if width > MAX_SIDE || height > MAX_SIDE {
+      return None;
+    }
+    let mut buf = Vec::with_capacity(width * height * 3);
+    for y in 0..height {
+      for x in 0..width {
+        buf.push((x * 255 / width) as u8);
+        buf.push((y * 255 / height) as u8);
+        buf.push(((x + y) * 127 / (width + height)) as u8 + 64);
+      }
+    }
+    Some(Cover {
+      width: width as u32,
+      height: height as u32,
+      pixels: buf,
+    })
+  }
+}
+
+impl Encoder for StegoEncoder {
+  fn encode(&self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let too_large = || StegoError::TooLarge {
+      len: data.len(),
+      max: self.max_payload().unwrap_or(0),
+    };
+    let (width, height, mut pixels) = match &self.cover {
+      Some(cover) => (cover.width, cover.height, cover.pixels.clone()),
+      None => {
+        let cover = self.gradient(data.len()).ok_or_else(too_large)?;
+        (cover.width, cover.height, cover.pixels)
+      }
+    };
+    if data.len() > self.capacity_of(width, height) {
+      Err(too_large())?;
+    }
+
+    let bits = self.bits as usize;
+    let mask = (1u8 << bits) - 1;
+    let per_byte = 8 / bits;
+    for (i, byte) in data.iter().enumerate() {
+      for j in 0..per_byte {
+        let shift = 8 - bits * (j + 1);
+        let channel = &mut pixels[i * per_byte + j];
+        *channel = (*channel & !mask) | ((byte >> shift) & mask);
+      }
+    }
+
+    let mut cursor = Cursor::new(Vec::new());
+    {
+      let mut enc = png::Encoder::new(&mut cursor, width, height);
+      enc.set_depth(BitDepth::Eight);
+      enc.set_color(ColorType::Rgb);
+      enc.set_compression(Compression::Default);
+      let mut writer = enc.write_header().map_err(StegoError::Encoding)?;
+      let mut stream = writer.stream_writer().map_err(StegoError::Encoding)?;
+      stream.write_all(&pixels)?;
+      stream.finish().map_err(StegoError::Encoding)?;
+    }
+    Ok(cursor.into_inner())
+  }
+
+  fn decode(&self, data: &[u8], data_len: usize) -> anyhow::Result<Vec<u8>> {
+    let image = Cover::load(data)?;
+    let bits = self.bits as usize;
+    let mask = (1u8 << bits) - 1;
+    let per_byte = 8 / bits;
+    let channels = image
+      .pixels
+      .get(..data_len * per_byte)
+      .ok_or(StegoError::OutOfBound {
+        size: data_len,
+        bound: self.capacity_of(image.width, image.height),
+      })?;
+    Ok(
+      channels
+        .chunks_exact(per_byte)
+        .map(|chunk| {
+          chunk
+            .iter()
+            .fold(0, |byte, channel| (byte << bits) | (channel & mask))
+        })
+        .collect(),
+    )
+  }
+
+  fn max_payload(&self) -> Option<usize> {
+    match &self.cover {
+      Some(cover) => Some(self.capacity_of(cover.width, cover.height)),
+      None => Some(self.capacity_of(MAX_SIDE as u32, MAX_SIDE as u32)),
+    }
+  }
+
+  /// The generated cover takes `8 / bits` channels per byte, a given cover has a fixed size
+  fn max_payload_for(&self, file_bytes: u64) -> Option<usize> {
+    match &self.cover {
+      Some(_) => None,
+      None => Some(
+        (raw_bytes_within(file_bytes) * self.bits as usize / 8)
+          .min(self.capacity_of(MAX_SIDE as u32, MAX_SIDE as u32)),
+      ),
+    }
+  }
+}
+
+impl Cover {
+  /// Load a PNG or JPEG image as RGB
+  fn load(data: &[u8]) -> Result<Cover, StegoError> {
+    if data.starts_with(&[0x89, b'P', b'N', b'G']) {
+      Cover::load_png(data)
+    } else if data.starts_with(&[0xff, 0xd8]) {
+      Cover::load_jpeg(data)
+    } else {
+      Err(StegoError::UnknownFormat)
+    }
+  }
+
+  fn load_png(data: &[u8]) -> Result<Cover, StegoError> {
+    let mut decoder = png::Decoder::new(data);
+    decoder.set_transformations(Transformations::EXPAND | Transformations::STRIP_16);
+    let mut reader = decoder.read_info()?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf)?;
+    buf.truncate(info.buffer_size());
+    let pixels = match info.color_type {
+      ColorType::Rgb => buf,
+      ColorType::Rgba => buf
+        .chunks_exact(4)
+        .flat_map(|i| [i[0], i[1], i[2]])
+        .collect(),
+      ColorType::Grayscale | ColorType::Indexed => buf.iter().flat_map(|&i| [i, i, i]).collect(),
+      ColorType::GrayscaleAlpha => buf
+        .chunks_exact(2)
+        .flat_map(|i| [i[0], i[0], i[0]])
+        .collect(),
+    };
+    Ok(Cover {
+      width: info.width,
+      height: info.height,
+      pixels,
+    })
+  }
+
+  fn load_jpeg(data: &[u8]) -> Result<Cover, StegoError> {
+    let mut decoder = jpeg_decoder::Decoder::new(data);
+    let buf = decoder.decode()?;
+    let info = decoder.info().ok_or(StegoError::UnknownFormat)?;
+    let pixels = match info.pixel_format {
+      jpeg_decoder::PixelFormat::RGB24 => buf,
+      jpeg_decoder::PixelFormat::L8 => buf.iter().flat_map(|&i| [i, i, i]).collect(),
+      _ => Err(StegoError::UnknownFormat)?,
+    };
+    Ok(Cover {
+      width: info.width as u32,
+      height: info.height as u32,
+      pixels,
+    })
+  }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum StegoError {
+  #[error(
+    "Input is too large to hide in the cover, {} KiB / {} KiB ",
+    *len as f64 / 1024.0,
+    *max as f64 / 1024.0)
+  ]
+  TooLarge { len: usize, max: usize },
+  #[error("Size {size} out of bound {bound}")]
+  OutOfBound { size: usize, bound: usize },
+  #[error("Unsupported low bits {0}, should be one of 1, 2, 4")]
+  Bits(u8),
+  #[error("Unknown image format, only PNG and JPEG are supported")]
+  UnknownFormat,
+  #[error("An encoding error occurred during png enc/dec {0}")]
+  Encoding(
+    #[from]
+    #[source]
+    EncodingError,
+  ),
+  #[error("An encoding error occurred during png enc/dec {0}")]
+  Decoding(
+    #[from]
+    #[source]
+    DecodingError,
+  ),
+  #[error("An error occurred during jpeg decoding {0}")]
+  Jpeg(
+    #[from]
+    #[source]
+    jpeg_decoder::Error,
+  ),
+  #[error("An I/O error occurred during png enc/dec {0}")]
+  Io(
+    #[from]
+    #[source]
+    std::io::Error,
+  ),
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+
+  use crate::encoder::stego::{Cover, StegoEncoder};
+  use crate::encoder::Encoder;
+
+  fn sample(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 131 % 251) as u8).collect()
+  }
+
+  #[test]
+  fn encode_decode_gradient() {
+    for bits in [1, 2, 4] {
+      let enc = StegoEncoder::new(bits).unwrap();
+      for len in [0, 1, 3, 114514] {
+        let data = sample(len);
+        let encoded = enc.encode(&data).unwrap();
+        assert_eq!(enc.decode(&encoded, len).unwrap(), data);
+      }
+    }
+  }
+
+  #[test]
+  fn encode_decode_cover() {
+    let mut enc = StegoEncoder::new(2).unwrap();
+    let cover = enc.gradient(4096).unwrap();
+    let original = cover.pixels.clone();
+    enc.cover = Some(Arc::new(cover));
+    assert!(enc.max_payload().unwrap() >= 4096);
+
+    let data = sample(1000);
+    let encoded = enc.encode(&data).unwrap();
+    assert_eq!(enc.decode(&encoded, data.len()).unwrap(), data);
+
+    // Only low bits are touched
+    let stego = Cover::load(&encoded).unwrap();
+    assert!(stego
+      .pixels
+      .iter()
+      .zip(original.iter())
+      .all(|(a, b)| a >> 2 == b >> 2));
+
+    let too_large = sample(enc.max_payload().unwrap() + 1);
+    assert!(enc.encode(&too_large).is_err());
+  }
+
+  #[test]
+  fn invalid_bits() {
+    assert!(StegoEncoder::new(3).is_err());
+    assert!(StegoEncoder::new(0).is_err());
+  }
+}
@@ -0,0 +1,139 @@
+use std::io::Cursor;
+
+use image_webp::{ColorType, DecodingError, EncodingError, WebPDecoder, WebPEncoder};
+
+use super::{raw_bytes_within, Encoder};
+
+/// Max side length of a WebP image
+const MAX_SIDE: u32 = 16383;
+
+/// Every pixel is stored as RGB, alpha is not used to avoid
+/// decoders dropping the alpha channel of fully opaque images.
+const BYTES_PER_PIXEL: usize = 3;
+
+pub struct WebpEncoder();
+
+impl Encoder for WebpEncoder {
+  fn encode(&self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let (width, height) = geometry(data.len()).ok_or(WebpError::TooLarge {
+      len: data.len(),
+      max: MAX_SIDE as usize * MAX_SIDE as usize * BYTES_PER_PIXEL,
+    })?;
+    let size = width as usize * height as usize * BYTES_PER_PIXEL;
+    let mut padded = Vec::with_capacity(size);
+    padded.extend_from_slice(data);
+    padded.resize(size, 0);
+
+    let mut cursor = Cursor::new(Vec::new());
+    WebPEncoder::new(&mut cursor)
+      .encode(&padded, width, height, ColorType::Rgb8)
+      .map_err(WebpError::Encoding)?;
+    Ok(cursor.into_inner())
+  }
+
+  fn decode(&self, data: &[u8], data_len: usize) -> anyhow::Result<Vec<u8>> {
+    let mut decoder = WebPDecoder::new(Cursor::new(data)).map_err(WebpError::Decoding)?;
+    if decoder.is_lossy() {
+      Err(WebpError::Lossy)?;
+    }
+    let size = decoder.output_buffer_size().ok_or(WebpError::OutOfBound {
+      size: data_len,
+      bound: usize::MAX,
+    })?;
+    let mut buf = vec![0; size];
+    decoder.read_image(&mut buf).map_err(WebpError::Decoding)?;
+    if decoder.has_alpha() {
+      // Drop the alpha channel, in case the host re-encoded it as RGBA
+      buf = buf
+        .chunks_exact(4)
+        .flat_map(|pixel| pixel[..3].to_vec())
+        .collect();
+    }
+    if buf.len() < data_len {
+      Err(WebpError::OutOfBound {
+        size: data_len,
+        bound: buf.len(),
+      })?;
+    }
+    buf.truncate(data_len);
+    Ok(buf)
+  }
+
+  fn max_payload(&self) -> Option<usize> {
+    Some(MAX_SIDE as usize * MAX_SIDE as usize * BYTES_PER_PIXEL)
+  }
+
+  /// Huffman codes of lossless WebP average at most 8 bits, like raw bytes
+  fn max_payload_for(&self, file_bytes: u64) -> Option<usize> {
+    Some(raw_bytes_within(file_bytes).min(MAX_SIDE as usize * MAX_SIDE as usize * BYTES_PER_PIXEL))
+  }
+}
+
+/// Compute a near-square `(width, height)` which can hold `data_len` bytes
+///
+/// # Return
+///
+/// Return [None] if `data_len` exceeds the max size of a WebP image
+fn geometry(data_len: usize) -> Option<(u32, u32)> {
+  let pixels = data_len.max(1).div_ceil(BYTES_PER_PIXEL);
+  let width = ((pixels as f64).sqrt().ceil() as usize).min(MAX_SIDE as usize);
+  let height = pixels.div_ceil(width);
+  if height > MAX_SIDE as usize {
+    return None;
+  }
+  Some((width as u32, height as u32))
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum WebpError {
+  #[error(
+    "Input is too large to process, {} KiB / {} KiB ",
+    *len as f64 / 1024.0,
+    *max as f64 / 1024.0)
+  ]
+  TooLarge { len: usize, max: usize },
+  #[error("Size {size} out of bound {bound}")]
+  OutOfBound { size: usize, bound: usize },
+  #[error("The image is lossy WebP, data is corrupted")]
+  Lossy,
+  #[error("An encoding error occurred during webp enc/dec {0}")]
+  Encoding(
+    #[from]
+    #[source]
+    EncodingError,
+  ),
+  #[error("An encoding error occurred during webp enc/dec {0}")]
+  Decoding(
+    #[from]
+    #[source]
+    DecodingError,
+  ),
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::encoder::webp::{geometry, WebpEncoder, BYTES_PER_PIXEL};
+  use crate::encoder::Encoder;
+
+  #[test]
+  fn geometry_should_fit() {
+    for len in [1, 2, 3, 4, 1000, 16384, 1024 * 1024, 12 * 1024 * 1024] {
+      let (width, height) = geometry(len).unwrap();
+      let size = width as usize * height as usize * BYTES_PER_PIXEL;
+      assert!(size >= len);
+      assert!(size - len < width as usize * BYTES_PER_PIXEL);
+    }
+    assert_eq!(geometry(16383 * 16383 * 3 + 1), None);
+  }
+
+  #[test]
+  fn encode_decode_webp() {
+    let enc = WebpEncoder();
+    for len in [1, 4, 114514, 1024 * 1024] {
+      let data: Vec<u8> = (0..len).map(|i| (i * 7 % 251) as u8).collect();
+      let encoded = enc.encode(&data).unwrap();
+      let decoded = enc.decode(&encoded, data.len()).unwrap();
+      assert_eq!(decoded, data);
+    }
+  }
+}
@@ -23,6 +23,38 @@ impl RangedBytesValueParser {
   }
 }
 
+/// Parse bytes like `4 MiB`, `512k` or `1024`
+pub fn parse_bytes(raw: &str) -> Result<u64, String> {
+  let caps = if let Some(caps) = RE.captures(raw.trim()) {
+    caps
+  } else {
+    return Err("Syntax: Number + Unit, Units: [K, M, Ki, Mi, KiB, MiB]".to_string());
+  };
+  let multiple = if let Some(matched) = &caps.name("unit") {
+    match matched.as_str().to_ascii_lowercase().as_str() {
+      "m" => 1024 * 1024,
+      "k" => 1024,
+      _ => 1,
+    }
+  } else {
+    1
+  };
+  let num = u64::from_str(&caps["num"]).map_err(|e| format!("Failed to parse number {}", e))?;
+  num
+    .checked_mul(multiple)
+    .ok_or_else(|| format!("Value too large, {} * {}", num, multiple))
+}
+
+/// Parse bytes per second, in the syntax of [parse_bytes] with an optional `/s` suffix
+pub fn parse_rate(raw: &str) -> Result<u64, String> {
+  let raw = raw.trim();
+  let bytes = match raw.len().checked_sub(2) {
+    Some(i) if raw.is_char_boundary(i) && raw[i..].eq_ignore_ascii_case("/s") => &raw[..i],
+    _ => raw,
+  };
+  parse_bytes(bytes)
+}
+
 #[test]
 fn test() {
   dbg!(RE.find("4 MiB"));
@@ -38,39 +70,76 @@ impl TypedValueParser for RangedBytesValueParser {
     value: &OsStr,
   ) -> Result<Self::Value, clap::Error> {
     let raw = StringValueParser::new().parse_ref(cmd, arg, value)?;
-    let caps = if let Some(caps) = RE.captures(&*raw.trim()) {
-      caps
-    } else {
-      return Err(clap::Error::raw(
-        ErrorKind::InvalidValue,
-        "Syntax: Number + Unit, Units: [K, M, Ki, Mi, KiB, MiB]".to_string(),
-      ));
-    };
-    let multiple = if let Some(matched) = &caps.name("unit") {
-      match matched.as_str().to_ascii_lowercase().as_str() {
-        "m" => 1024 * 1024,
-        "k" => 1024,
-        _ => 1,
-      }
-    } else {
-      1
-    };
-    let num = u64::from_str(&caps["num"]).map_err(|e| {
-      clap::Error::raw(
-        ErrorKind::InvalidValue,
-        format!("Failed to parse number {}", e),
-      )
-    })?;
-    let value = if let Some(result) = num.checked_mul(multiple) {
-      result
-    } else {
-      return Err(clap::Error::raw(
-        ErrorKind::InvalidValue,
-        format!("Value too large, {} * {}", num, multiple),
-      ));
-    };
+    let value = parse_bytes(&raw).map_err(|e| clap::Error::raw(ErrorKind::InvalidValue, e))?;
+    self
+      .ranged_u64_parser
+      .parse(cmd, arg, OsString::from(value.to_string()))
+  }
+}
+
+/// Bytes per second, in the syntax of [RangedBytesValueParser] with an optional `/s` suffix
+#[derive(Copy, Clone, Debug)]
+pub struct RateValueParser {
+  pub bytes_parser: RangedBytesValueParser,
+}
+
+impl RateValueParser {
+  pub fn new<B: RangeBounds<u64>>(range: B) -> RateValueParser {
+    RateValueParser {
+      bytes_parser: RangedBytesValueParser::new(range),
+    }
+  }
+}
+
+impl TypedValueParser for RateValueParser {
+  type Value = u64;
+
+  fn parse_ref(
+    &self,
+    cmd: &Command,
+    arg: Option<&Arg>,
+    value: &OsStr,
+  ) -> Result<Self::Value, clap::Error> {
+    let raw = StringValueParser::new().parse_ref(cmd, arg, value)?;
+    let value = parse_rate(&raw).map_err(|e| clap::Error::raw(ErrorKind::InvalidValue, e))?;
     self
+      .bytes_parser
       .ranged_u64_parser
       .parse(cmd, arg, OsString::from(value.to_string()))
   }
 }
+
+#[test]
+fn rate_with_suffix() {
+  let cmd = Command::new("cutis").arg(Arg::new("rate").value_parser(RateValueParser::new(1..)));
+  for (raw, expected) in [
+    ("2MiB/s", 2 * 1024 * 1024),
+    ("512 KiB/S", 512 * 1024),
+    ("100", 100),
+  ] {
+    let matches = cmd.clone().try_get_matches_from(["cutis", raw]).unwrap();
+    assert_eq!(matches.get_one::<u64>("rate"), Some(&expected));
+  }
+  assert!(cmd.try_get_matches_from(["cutis", "2MiB/h"]).is_err());
+}
+
+/// Parse an account name, used as a file name, so only `[A-Za-z0-9_-]` are allowed
+pub fn parse_account(raw: &str) -> Result<String, String> {
+  let valid = (1..=32).contains(&raw.len())
+    && raw
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+  if valid {
+    Ok(raw.to_string())
+  } else {
+    Err("Account name should be 1 to 32 characters of [A-Za-z0-9_-]".to_string())
+  }
+}
+
+#[test]
+fn account_name() {
+  assert_eq!(parse_account("work_2-b").unwrap(), "work_2-b");
+  for raw in ["", "../etc", "a b", "账号", &"a".repeat(33)] {
+    assert!(parse_account(raw).is_err(), "{raw}");
+  }
+}
@@ -0,0 +1,258 @@
+//! Show QR codes of login urls, in the terminal, as a PNG file or on a local web page
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use qrcode::{Color, QrCode};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+/// Pixels per module
+const SCALE: usize = 8;
+/// Modules of the quiet zone around the code
+const QUIET_ZONE: usize = 4;
+/// Drop a connection of the page if it doesn't finish in time
+const RESPOND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How to show the QR code for login
+#[derive(Clone, Debug)]
+pub struct QrOptions {
+  /// Write the QR code to a PNG file
+  pub output: Option<PathBuf>,
+  /// Serve the QR code on a local web page
+  pub serve: Option<SocketAddr>,
+  /// Print the raw url
+  pub print_url: bool,
+  /// Give up if not confirmed in time
+  pub timeout: Duration,
+}
+
+impl Default for QrOptions {
+  fn default() -> Self {
+    QrOptions {
+      output: None,
+      serve: None,
+      print_url: false,
+      timeout: Duration::from_secs(120),
+    }
+  }
+}
+
+/// Server of the QR code page, stopped on drop
+pub struct QrDisplay {
+  server: Option<JoinHandle<()>>,
+}
+
+impl Drop for QrDisplay {
+  fn drop(&mut self) {
+    if let Some(server) = &self.server {
+      server.abort();
+    }
+  }
+}
+
+/// Show the QR code of `url` as `options` asks, in the terminal if neither file nor page is
+/// asked. Keep the returned value until the login is done.
+pub async fn show(url: &str, options: &QrOptions) -> Result<QrDisplay> {
+  let headless = options.output.is_some() || options.serve.is_some();
+  let mut print_url = options.print_url;
+  if !headless && qr2term::print_qr(url).is_err() {
+    warn!("Failed to print qrcode in terminal, please open the url below in a QR code generator");
+    print_url = true;
+  }
+  if print_url {
+    info!("Login url: {url}");
+  }
+  let mut display = QrDisplay { server: None };
+  if headless {
+    let png = render_png(url)?;
+    if let Some(path) = &options.output {
+      std::fs::write(path, &png).with_context(|| format!("Failed to write qrcode to {path:?}"))?;
+      info!("QR code is written to {path:?}");
+    }
+    if let Some(addr) = options.serve {
+      let (addr, server) = serve(addr, png, url.to_string()).await?;
+      info!("QR code is served at http://{addr}/");
+      display.server = Some(server);
+    }
+  }
+  Ok(display)
+}
+
+/// Render a grayscale PNG of the QR code of `data`
+pub fn render_png(data: &str) -> Result<Vec<u8>> {
+  let code = QrCode::new(data.as_bytes()).context("Failed to encode qrcode")?;
+  let modules = code.width();
+  let colors = code.to_colors();
+  let side = (modules + QUIET_ZONE * 2) * SCALE;
+  let mut pixels = vec![0xFFu8; side * side];
+  for (i, color) in colors.iter().enumerate() {
+    if *color != Color::Dark {
+      continue;
+    }
+    let (x, y) = (i % modules + QUIET_ZONE, i / modules + QUIET_ZONE);
+    for row in y * SCALE..(y + 1) * SCALE {
+      pixels[row * side + x * SCALE..row * side + (x + 1) * SCALE].fill(0);
+    }
+  }
+  let mut png = Vec::new();
+  {
+    let mut encoder = png::Encoder::new(&mut png, side as u32, side as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&pixels)?;
+  }
+  Ok(png)
+}
+
+/// Serve a page showing `png` and `url` at `addr`, return the bound address
+pub async fn serve(
+  addr: SocketAddr,
+  png: Vec<u8>,
+  url: String,
+) -> Result<(SocketAddr, JoinHandle<()>)> {
+  let listener = TcpListener::bind(addr)
+    .await
+    .with_context(|| format!("Failed to listen on {addr}"))?;
+  let addr = listener.local_addr()?;
+  let page = format!(
+    "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Cutis Login</title></head>\
+    <body style=\"text-align:center\"><img src=\"/qr.png\" alt=\"QR code\">\
+    <p>Scan with the app and confirm</p><p><a href=\"{url}\">{url}</a></p></body></html>",
+    url = html_escape(&url),
+  );
+  let page: Arc<str> = page.into();
+  let png: Arc<[u8]> = png.into();
+  let server = tokio::spawn(async move {
+    loop {
+      let (stream, peer) = match listener.accept().await {
+        Ok(accepted) => accepted,
+        Err(err) => {
+          warn!("Failed to accept connection: {err}");
+          continue;
+        }
+      };
+      debug!("QR code page requested by {peer}");
+      let (page, png) = (page.clone(), png.clone());
+      // A slow client mustn't block the others
+      tokio::spawn(async move {
+        match tokio::time::timeout(RESPOND_TIMEOUT, respond(stream, &page, &png)).await {
+          Ok(Ok(())) => {}
+          Ok(Err(err)) => debug!("Failed to respond {peer}: {err}"),
+          Err(_) => debug!("Timed out responding {peer}"),
+        }
+      });
+    }
+  });
+  Ok((addr, server))
+}
+
+async fn respond(mut stream: TcpStream, page: &str, png: &[u8]) -> std::io::Result<()> {
+  let mut buf = [0u8; 1024];
+  let len = stream.read(&mut buf).await?;
+  let request = String::from_utf8_lossy(&buf[..len]);
+  let path = request.split_whitespace().nth(1).unwrap_or("/");
+  let (status, content_type, body) = match path {
+    "/" | "/index.html" => ("200 OK", "text/html; charset=utf-8", page.as_bytes()),
+    "/qr.png" => ("200 OK", "image/png", png),
+    _ => ("404 Not Found", "text/plain", &b"Not Found"[..]),
+  };
+  let header = format!(
+    "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n\
+    Cache-Control: no-store\r\nConnection: close\r\n\r\n",
+    body.len()
+  );
+  stream.write_all(header.as_bytes()).await?;
+  stream.write_all(body).await?;
+  stream.shutdown().await
+}
+
+fn html_escape(raw: &str) -> String {
+  raw
+    .replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::qr::{render_png, serve, QUIET_ZONE, SCALE};
+
+  const URL: &str = "https://passport.bilibili.com/qrcode/h5/login?oauthKey=ab&x=<y>";
+
+  #[test]
+  fn render_qrcode() {
+    let png = render_png(URL).unwrap();
+    let decoder = png::Decoder::new(png.as_slice());
+    let mut reader = decoder.read_info().unwrap();
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels).unwrap();
+    assert_eq!(info.width, info.height);
+    assert_eq!(info.width as usize % SCALE, 0);
+    // Quiet zone is white, the finder pattern at the top left is dark
+    assert_eq!(pixels[0], 0xFF);
+    let corner = QUIET_ZONE * SCALE;
+    assert_eq!(pixels[corner * info.width as usize + corner], 0);
+  }
+
+  #[tokio::test]
+  async fn serve_qrcode() {
+    let png = render_png(URL).unwrap();
+    let (addr, server) = serve("127.0.0.1:0".parse().unwrap(), png.clone(), URL.to_string())
+      .await
+      .unwrap();
+    let client = reqwest::Client::builder().no_proxy().build().unwrap();
+    let served = client
+      .get(format!("http://{addr}/qr.png"))
+      .send()
+      .await
+      .unwrap()
+      .bytes()
+      .await
+      .unwrap();
+    assert_eq!(served, png);
+    let page = client
+      .get(format!("http://{addr}/"))
+      .send()
+      .await
+      .unwrap()
+      .text()
+      .await
+      .unwrap();
+    assert!(page.contains("&lt;y&gt;"), "{page}");
+    let missing = client
+      .get(format!("http://{addr}/missing"))
+      .send()
+      .await
+      .unwrap();
+    assert_eq!(missing.status(), 404);
+    server.abort();
+  }
+
+  #[tokio::test]
+  async fn idle_client_blocks_no_other() {
+    let png = render_png(URL).unwrap();
+    let (addr, server) = serve("127.0.0.1:0".parse().unwrap(), png.clone(), URL.to_string())
+      .await
+      .unwrap();
+    // Connects but never sends a request
+    let _idle = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let client = reqwest::Client::builder().no_proxy().build().unwrap();
+    let served = tokio::time::timeout(
+      std::time::Duration::from_secs(5),
+      client.get(format!("http://{addr}/qr.png")).send(),
+    )
+    .await
+    .expect("blocked by the idle client")
+    .unwrap();
+    assert_eq!(served.bytes().await.unwrap(), png);
+    server.abort();
+  }
+}